@@ -0,0 +1,174 @@
+use glam::Vec3;
+
+use crate::framebuffer::Layer;
+use crate::renderer::PathSample;
+
+// Per pixel sums of the render passes, turned into framebuffer layers once all samples are in
+pub struct AovBuffers {
+    width: usize,
+    height: usize,
+    albedo: Vec<Vec3>,
+    normal: Vec<Vec3>,
+    position: Vec<Vec3>,
+    depth: Vec<f32>,
+    // camera samples that hit a surface, position and depth are averaged over these only
+    hits: Vec<u32>,
+    // ids can not be averaged, the first sample of each pixel that hits decides
+    object_id: Vec<Option<u32>>,
+    material_id: Vec<Option<u32>>,
+    direct_diffuse: Vec<Vec3>,
+    direct_specular: Vec<Vec3>,
+    indirect_diffuse: Vec<Vec3>,
+    indirect_specular: Vec<Vec3>,
+    samples: Vec<u32>,
+}
+
+impl AovBuffers {
+    pub fn new(width: usize, height: usize) -> Self {
+        let count = width * height;
+        AovBuffers {
+            width,
+            height,
+            albedo: vec![Vec3::ZERO; count],
+            normal: vec![Vec3::ZERO; count],
+            position: vec![Vec3::ZERO; count],
+            depth: vec![0.0; count],
+            hits: vec![0; count],
+            object_id: vec![None; count],
+            material_id: vec![None; count],
+            direct_diffuse: vec![Vec3::ZERO; count],
+            direct_specular: vec![Vec3::ZERO; count],
+            indirect_diffuse: vec![Vec3::ZERO; count],
+            indirect_specular: vec![Vec3::ZERO; count],
+            samples: vec![0; count],
+        }
+    }
+
+    pub fn add(&mut self, index: usize, sample: &PathSample) {
+        self.samples[index] += 1;
+
+        self.direct_diffuse[index] += sample.direct_diffuse;
+        self.direct_specular[index] += sample.direct_specular;
+        self.indirect_diffuse[index] += sample.indirect_diffuse;
+        self.indirect_specular[index] += sample.indirect_specular;
+
+        let Some(surface) = &sample.surface else {
+            return;
+        };
+        self.albedo[index] += surface.albedo;
+        self.normal[index] += surface.normal;
+        self.position[index] += surface.position;
+        self.depth[index] += surface.depth;
+        self.hits[index] += 1;
+
+        if self.object_id[index].is_none() {
+            self.object_id[index] = Some(surface.object_id);
+            self.material_id[index] = Some(surface.material_id);
+        }
+    }
+
+    // Averages the sums, misses leave zero albedo and normal and an infinite depth,
+    // ids are stored plus one so that 0 marks the background
+    pub fn into_layers(self, samples: u32) -> Vec<Layer> {
+        debug_assert!(self.samples.iter().all(|&count| count == samples));
+        let count = self.width * self.height;
+        let per_sample = 1.0 / samples.max(1) as f32;
+
+        let average = |sums: Vec<Vec3>| sums.into_iter().map(|v| v * per_sample).collect::<Vec<Vec3>>();
+        let over_hits = |sums: &[Vec3]| -> Vec<Vec3> {
+            sums.iter().zip(self.hits.iter()).map(|(&v, &hits)| if hits > 0 { v / hits as f32 } else { Vec3::ZERO }).collect()
+        };
+        let ids = |ids: &[Option<u32>]| -> Vec<Vec3> {
+            ids.iter().map(|id| Vec3::splat(id.map_or(0.0, |id| (id + 1) as f32))).collect()
+        };
+
+        let depth: Vec<Vec3> = (0..count)
+            .map(|i| Vec3::splat(if self.hits[i] > 0 { self.depth[i] / self.hits[i] as f32 } else { f32::INFINITY }))
+            .collect();
+        let normal: Vec<Vec3> = self.normal.iter().map(|n| n.normalize_or_zero()).collect();
+
+        vec![
+            Layer { name: "albedo".to_string(), channels: &["R", "G", "B"], pixels: average(self.albedo) },
+            Layer { name: "normal".to_string(), channels: &["X", "Y", "Z"], pixels: normal },
+            Layer { name: "position".to_string(), channels: &["X", "Y", "Z"], pixels: over_hits(&self.position) },
+            Layer { name: "depth".to_string(), channels: &["Z"], pixels: depth },
+            Layer { name: "object_id".to_string(), channels: &["X"], pixels: ids(&self.object_id) },
+            Layer { name: "material_id".to_string(), channels: &["X"], pixels: ids(&self.material_id) },
+            Layer { name: "direct_diffuse".to_string(), channels: &["R", "G", "B"], pixels: average(self.direct_diffuse) },
+            Layer { name: "direct_specular".to_string(), channels: &["R", "G", "B"], pixels: average(self.direct_specular) },
+            Layer { name: "indirect_diffuse".to_string(), channels: &["R", "G", "B"], pixels: average(self.indirect_diffuse) },
+            Layer { name: "indirect_specular".to_string(), channels: &["R", "G", "B"], pixels: average(self.indirect_specular) },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::SurfaceInfo;
+
+    fn sample(object_id: Option<u32>) -> PathSample {
+        PathSample {
+            color: Vec3::ZERO,
+            direct_diffuse: Vec3::ZERO,
+            direct_specular: Vec3::ZERO,
+            indirect_diffuse: Vec3::ZERO,
+            indirect_specular: Vec3::ZERO,
+            surface: object_id.map(|object_id| SurfaceInfo {
+                albedo: Vec3::ONE,
+                normal: Vec3::Y,
+                position: Vec3::ZERO,
+                depth: 2.0,
+                object_id,
+                material_id: object_id + 10,
+            }),
+        }
+    }
+
+    fn layer<'a>(layers: &'a [Layer], name: &str) -> &'a [Vec3] {
+        &layers.iter().find(|layer| layer.name == name).unwrap().pixels
+    }
+
+    #[test]
+    fn averages_over_samples_and_hits() {
+        let mut buffers = AovBuffers::new(1, 1);
+        let mut hit = sample(Some(0));
+        hit.direct_diffuse = Vec3::new(0.4, 0.0, 0.0);
+        buffers.add(0, &hit);
+        let mut other = sample(Some(0));
+        other.surface.as_mut().unwrap().normal = Vec3::X;
+        other.surface.as_mut().unwrap().depth = 4.0;
+        other.indirect_specular = Vec3::splat(0.6);
+        buffers.add(0, &other);
+        buffers.add(0, &sample(None));
+        buffers.add(0, &sample(None));
+        let layers = buffers.into_layers(4);
+
+        // light passes and albedo over every sample, a miss adds black
+        assert_eq!(layer(&layers, "direct_diffuse"), [Vec3::new(0.1, 0.0, 0.0)]);
+        assert_eq!(layer(&layers, "indirect_specular"), [Vec3::splat(0.15)]);
+        assert_eq!(layer(&layers, "albedo"), [Vec3::splat(0.5)]);
+        // geometry over the hits only, the normal renormalized
+        assert_eq!(layer(&layers, "depth"), [Vec3::splat(3.0)]);
+        assert!((layer(&layers, "normal")[0] - Vec3::new(1.0, 1.0, 0.0).normalize()).length() < 1e-6);
+        assert_eq!(layers.len(), 10);
+    }
+
+    #[test]
+    fn ids_come_from_the_first_hit() {
+        // the first sample escapes past the edge of object 3, later ones hit 3 and then 5
+        let mut buffers = AovBuffers::new(2, 1);
+        for id in [None, Some(3), Some(5)] {
+            buffers.add(0, &sample(id));
+        }
+        for _ in 0..3 {
+            buffers.add(1, &sample(None));
+        }
+        let layers = buffers.into_layers(3);
+
+        assert_eq!(layer(&layers, "object_id"), [Vec3::splat(4.0), Vec3::ZERO]);
+        assert_eq!(layer(&layers, "material_id"), [Vec3::splat(14.0), Vec3::ZERO]);
+        // depth is averaged over the hits only, misses stay infinitely far
+        assert_eq!(layer(&layers, "depth"), [Vec3::splat(2.0), Vec3::splat(f32::INFINITY)]);
+    }
+}
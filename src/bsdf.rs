@@ -0,0 +1,672 @@
+use glam::Vec3;
+use rand::prelude::*;
+
+use crate::primitives::primitives::*;
+
+pub struct BsdfSample {
+    pub direction: Vec3,
+    // bsdf * cos / pdf, the factor the radiance arriving from direction gets scaled by
+    pub weight: Vec3,
+    // delta lobes, light sampling can never hit these directions
+    pub specular: bool,
+    // solid angle density of picking direction, unused for specular samples
+    pub pdf: f32,
+    // the part of weight that comes from the diffuse and sheen lobes, the rest is specular
+    pub diffuse: Vec3,
+}
+
+// bsdf * cos split by lobe, diffuse holds the diffuse and sheen lobes, specular everything glossy
+pub struct BsdfEval {
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    // solid angle density of sample picking this direction
+    pub pdf: f32,
+}
+
+impl BsdfEval {
+    const ZERO: BsdfEval = BsdfEval { diffuse: Vec3::ZERO, specular: Vec3::ZERO, pdf: 0.0 };
+
+    pub fn value(&self) -> Vec3 {
+        self.diffuse + self.specular
+    }
+}
+
+// Reflectance at normal incidence of the clearcoat layer (ior 1.5)
+const CLEARCOAT_F0: f32 = 0.04;
+// below this the GGX distribution is too sharp for f32
+const MIN_ALPHA: f32 = 1e-3;
+
+// Picks the next bounce direction for a ray arriving along ray_direction,
+// None when the path gets absorbed
+// albedo is the surface color already converted to 0..1
+pub fn sample(material: &Material, hit: &HitRecord, albedo: Vec3, ray_direction: Vec3, rng: &mut ThreadRng) -> Option<BsdfSample> {
+    match material.kind {
+        MaterialKind::Standard => {
+            let frame = Frame::new(hit);
+            let wo = frame.to_local(-ray_direction);
+            if wo.z <= 0.0 {
+                return None;
+            }
+
+            let lobes = Principled::new(material, albedo, wo);
+            let choice = rng.random::<f32>();
+
+            // the transmission lobe is a smooth glass interface, handled like the dielectric material
+            if choice < lobes.p_transmission {
+                let eta = if hit.front_face { 1.0 / material.ior } else { material.ior };
+                let cos_i = wo.z.clamp(0.0, 1.0);
+                let scale = lobes.transmission_weight / lobes.p_transmission;
+
+                if rng.random::<f32>() < fresnel_dielectric(cos_i, eta) {
+                    return Some(BsdfSample { direction: ray_direction.reflect(hit.shading_normal), weight: Vec3::splat(scale), specular: true, pdf: 0.0, diffuse: Vec3::ZERO });
+                }
+                let direction = ray_direction.refract(hit.shading_normal, eta).normalize();
+                return Some(BsdfSample { direction, weight: lobes.base * scale, specular: true, pdf: 0.0, diffuse: Vec3::ZERO });
+            }
+
+            let choice = choice - lobes.p_transmission;
+            let wi = if choice < lobes.p_diffuse {
+                frame.to_local(sample_cosine_hemisphere(hit.shading_normal, rng))
+            } else if choice < lobes.p_diffuse + lobes.p_specular {
+                let h = sample_ggx_vndf(wo, lobes.alpha_x, lobes.alpha_y, rng.random(), rng.random());
+                (-wo).reflect(h)
+            } else {
+                let h = sample_ggx_vndf(wo, lobes.clearcoat_alpha, lobes.clearcoat_alpha, rng.random(), rng.random());
+                (-wo).reflect(h)
+            };
+
+            let direction = frame.to_world(wi);
+            if wi.z <= 0.0 || direction.dot(hit.geometric_normal) <= 0.0 {
+                return None;
+            }
+
+            // weight against the pdf of all non delta lobes together
+            let eval = lobes.eval(material, wo, wi);
+            if eval.pdf <= 0.0 {
+                return None;
+            }
+
+            Some(BsdfSample { direction, weight: eval.value() / eval.pdf, specular: false, pdf: eval.pdf, diffuse: eval.diffuse / eval.pdf })
+        }
+        MaterialKind::Conductor { eta, k } => {
+            let frame = Frame::new(hit);
+            let wo = frame.to_local(-ray_direction);
+            if wo.z <= 0.0 {
+                return None;
+            }
+
+            let alpha = roughness_to_alpha(material.roughness);
+            let h = sample_ggx_vndf(wo, alpha, alpha, rng.random(), rng.random());
+            let wi = (-wo).reflect(h);
+
+            let direction = frame.to_world(wi);
+            if wi.z <= 0.0 || direction.dot(hit.geometric_normal) <= 0.0 {
+                return None;
+            }
+
+            let eval = eval_conductor(material.roughness, eta, k, wo, wi);
+            if eval.pdf <= 0.0 {
+                return None;
+            }
+
+            Some(BsdfSample { direction, weight: eval.specular / eval.pdf, specular: false, pdf: eval.pdf, diffuse: Vec3::ZERO })
+        }
+        MaterialKind::Dielectric { ior, .. } => {
+            let n = hit.shading_normal;
+            // relative index of refraction from the incoming side to the other side
+            let eta = if hit.front_face { 1.0 / ior } else { ior };
+            let cos_i = (-ray_direction).dot(n).clamp(0.0, 1.0);
+
+            // choose between reflection and refraction by the fresnel term
+            if rng.random::<f32>() < fresnel_dielectric(cos_i, eta) {
+                Some(BsdfSample { direction: ray_direction.reflect(n), weight: Vec3::ONE, specular: true, pdf: 0.0, diffuse: Vec3::ZERO })
+            } else {
+                Some(BsdfSample { direction: ray_direction.refract(n, eta).normalize(), weight: albedo, specular: true, pdf: 0.0, diffuse: Vec3::ZERO })
+            }
+        }
+    }
+}
+
+// bsdf * cos and the sampling pdf for light arriving from light_direction,
+// delta lobes always return zero
+pub fn eval(material: &Material, hit: &HitRecord, albedo: Vec3, ray_direction: Vec3, light_direction: Vec3) -> BsdfEval {
+    match material.kind {
+        MaterialKind::Standard => {
+            // light from below the geometric surface would leak through
+            if light_direction.dot(hit.geometric_normal) <= 0.0 {
+                return BsdfEval::ZERO;
+            }
+
+            let frame = Frame::new(hit);
+            let wo = frame.to_local(-ray_direction);
+            let wi = frame.to_local(light_direction);
+            if wo.z <= 0.0 || wi.z <= 0.0 {
+                return BsdfEval::ZERO;
+            }
+
+            Principled::new(material, albedo, wo).eval(material, wo, wi)
+        }
+        MaterialKind::Conductor { eta, k } => {
+            if light_direction.dot(hit.geometric_normal) <= 0.0 {
+                return BsdfEval::ZERO;
+            }
+
+            let frame = Frame::new(hit);
+            let wo = frame.to_local(-ray_direction);
+            let wi = frame.to_local(light_direction);
+            if wo.z <= 0.0 || wi.z <= 0.0 {
+                return BsdfEval::ZERO;
+            }
+
+            eval_conductor(material.roughness, eta, k, wo, wi)
+        }
+        MaterialKind::Dielectric { .. } => BsdfEval::ZERO,
+    }
+}
+
+// GGX reflection with the conductor fresnel term, sampled with visible normals only
+fn eval_conductor(roughness: f32, eta: Vec3, k: Vec3, wo: Vec3, wi: Vec3) -> BsdfEval {
+    let alpha = roughness_to_alpha(roughness);
+    let h = (wo + wi).normalize();
+    let cos_d = wo.dot(h).max(0.0);
+
+    let fresnel = Vec3::new(
+        fresnel_conductor(cos_d, eta.x, k.x),
+        fresnel_conductor(cos_d, eta.y, k.y),
+        fresnel_conductor(cos_d, eta.z, k.z),
+    );
+    let d = ggx_d(h, alpha, alpha);
+    let g2 = 1.0 / (1.0 + smith_lambda(wo, alpha, alpha) + smith_lambda(wi, alpha, alpha));
+
+    let value = fresnel * (d * g2 / (4.0 * wo.z));
+    let pdf = ggx_vndf_pdf(wo, h, alpha, alpha) / (4.0 * cos_d.max(1e-6));
+
+    BsdfEval { diffuse: Vec3::ZERO, specular: value, pdf }
+}
+
+// Lobe weights and sampling probabilities of the principled standard material,
+// loosely following Burley 2012 / 2015
+struct Principled {
+    base: Vec3,
+    diffuse_weight: f32,
+    specular_weight: f32,
+    transmission_weight: f32,
+    specular_f0: Vec3,
+    sheen_color: Vec3,
+    alpha_x: f32,
+    alpha_y: f32,
+    clearcoat_alpha: f32,
+    p_diffuse: f32,
+    p_specular: f32,
+    p_clearcoat: f32,
+    p_transmission: f32,
+}
+
+impl Principled {
+    fn new(material: &Material, base: Vec3, wo: Vec3) -> Self {
+        let metallic = material.metallic.clamp(0.0, 1.0);
+        let transmission = material.transmission.clamp(0.0, 1.0);
+
+        let diffuse_weight = (1.0 - metallic) * (1.0 - transmission);
+        let transmission_weight = (1.0 - metallic) * transmission;
+        // glass brings its own reflection, so the specular layer fades out under transmission
+        let specular_weight = 1.0 - transmission_weight;
+
+        // hue of the base color without its brightness
+        let base_luminance = luminance(base);
+        let tint = if base_luminance > 0.0 { base / base_luminance } else { Vec3::ONE };
+
+        let dielectric_f0 = Vec3::ONE.lerp(tint, material.specular_tint) * (0.08 * material.specular);
+        let specular_f0 = dielectric_f0.lerp(base, metallic);
+        let sheen_color = Vec3::ONE.lerp(tint, material.sheen_tint) * material.sheen;
+
+        // anisotropy stretches the highlight along the tangent
+        let alpha = roughness_to_alpha(material.roughness);
+        let aspect = (1.0 - 0.9 * material.anisotropic.clamp(0.0, 1.0)).sqrt();
+
+        // sampling effort follows each lobe's rough share of the reflected energy
+        let specular_energy = luminance(fresnel_schlick_color(wo.z, specular_f0)) * specular_weight;
+        let clearcoat_energy = material.clearcoat * fresnel_schlick(wo.z, CLEARCOAT_F0);
+        let diffuse_energy = diffuse_weight * (luminance(base) + luminance(sheen_color));
+        let transmission_energy = transmission_weight * luminance(base).max(0.1);
+
+        let total = specular_energy + clearcoat_energy + diffuse_energy + transmission_energy;
+        let (p_diffuse, p_specular, p_clearcoat, p_transmission) = if total > 0.0 {
+            (diffuse_energy / total, specular_energy / total, clearcoat_energy / total, transmission_energy / total)
+        } else {
+            (1.0, 0.0, 0.0, 0.0)
+        };
+
+        Principled {
+            base,
+            diffuse_weight,
+            specular_weight,
+            transmission_weight,
+            specular_f0,
+            sheen_color,
+            alpha_x: (alpha / aspect).max(MIN_ALPHA),
+            alpha_y: (alpha * aspect).max(MIN_ALPHA),
+            clearcoat_alpha: roughness_to_alpha(material.clearcoat_roughness),
+            p_diffuse,
+            p_specular,
+            p_clearcoat,
+            p_transmission,
+        }
+    }
+
+    // bsdf * cos and pdf of the non delta lobes, directions in the local shading frame
+    fn eval(&self, material: &Material, wo: Vec3, wi: Vec3) -> BsdfEval {
+        let h = (wo + wi).normalize();
+        let cos_d = wi.dot(h).max(0.0);
+        let mut diffuse = Vec3::ZERO;
+        let mut specular = Vec3::ZERO;
+        let mut pdf = 0.0;
+
+        if self.diffuse_weight > 0.0 {
+            // Burley diffuse with retro reflection, blended towards the flattened subsurface lobe
+            let fl = (1.0 - wi.z).powi(5);
+            let fv = (1.0 - wo.z).powi(5);
+            let rr = material.roughness * cos_d * cos_d;
+
+            let fd90 = 0.5 + 2.0 * rr;
+            let burley = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+
+            let fss = (1.0 + (rr - 1.0) * fl) * (1.0 + (rr - 1.0) * fv);
+            let subsurface = 1.25 * (fss * (1.0 / (wi.z + wo.z) - 0.5) + 0.5);
+
+            let lambert = burley + (subsurface - burley) * material.subsurface.clamp(0.0, 1.0);
+            let sheen = self.sheen_color * (1.0 - cos_d).powi(5);
+
+            diffuse += (self.base * (lambert / std::f32::consts::PI) + sheen) * self.diffuse_weight;
+        }
+        pdf += self.p_diffuse * wi.z / std::f32::consts::PI;
+
+        if self.specular_weight > 0.0 {
+            let d = ggx_d(h, self.alpha_x, self.alpha_y);
+            let g2 = 1.0 / (1.0 + smith_lambda(wo, self.alpha_x, self.alpha_y) + smith_lambda(wi, self.alpha_x, self.alpha_y));
+            let fresnel = fresnel_schlick_color(cos_d, self.specular_f0);
+            specular += fresnel * (d * g2 / (4.0 * wo.z * wi.z)) * self.specular_weight;
+        }
+        pdf += self.p_specular * ggx_vndf_pdf(wo, h, self.alpha_x, self.alpha_y) / (4.0 * cos_d.max(1e-6));
+
+        if material.clearcoat > 0.0 {
+            let a = self.clearcoat_alpha;
+            let d = ggx_d(h, a, a);
+            let g2 = 1.0 / (1.0 + smith_lambda(wo, a, a) + smith_lambda(wi, a, a));
+            let fresnel = fresnel_schlick(cos_d, CLEARCOAT_F0);
+            specular += Vec3::splat(material.clearcoat * fresnel * d * g2 / (4.0 * wo.z * wi.z));
+        }
+        pdf += self.p_clearcoat * ggx_vndf_pdf(wo, h, self.clearcoat_alpha, self.clearcoat_alpha) / (4.0 * cos_d.max(1e-6));
+
+        BsdfEval { diffuse: diffuse * wi.z, specular: specular * wi.z, pdf }
+    }
+}
+
+// Power heuristic weight for a sample taken with pdf_a when pdf_b could also have produced it,
+// an infinite pdf_a marks a delta distribution the other strategy can never hit
+pub fn power_heuristic(pdf_a: f32, pdf_b: f32) -> f32 {
+    if pdf_a.is_infinite() {
+        return 1.0;
+    }
+    if pdf_a <= 0.0 {
+        return 0.0;
+    }
+    // written with the ratio so very peaked lobes do not overflow the squares
+    let ratio = pdf_b / pdf_a;
+    1.0 / (1.0 + ratio * ratio)
+}
+
+pub fn roughness_to_alpha(roughness: f32) -> f32 {
+    (roughness * roughness).max(MIN_ALPHA)
+}
+
+pub fn luminance(c: Vec3) -> f32 {
+    c.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+pub fn fresnel_schlick(cos_theta: f32, f0: f32) -> f32 {
+    f0 + (1.0 - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+pub fn fresnel_schlick_color(cos_theta: f32, f0: Vec3) -> Vec3 {
+    f0 + (Vec3::ONE - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+// Trowbridge-Reitz / GGX normal distribution, h in the local frame
+pub fn ggx_d(h: Vec3, alpha_x: f32, alpha_y: f32) -> f32 {
+    if h.z <= 0.0 {
+        return 0.0;
+    }
+    let t = (h.x / alpha_x).powi(2) + (h.y / alpha_y).powi(2) + h.z * h.z;
+    1.0 / (std::f32::consts::PI * alpha_x * alpha_y * t * t)
+}
+
+// Smith masking auxiliary function, G1 = 1 / (1 + lambda)
+pub fn smith_lambda(v: Vec3, alpha_x: f32, alpha_y: f32) -> f32 {
+    let cos2 = v.z * v.z;
+    if cos2 <= 0.0 {
+        return f32::INFINITY;
+    }
+    let tan2 = ((alpha_x * v.x).powi(2) + (alpha_y * v.y).powi(2)) / cos2;
+    0.5 * (-1.0 + (1.0 + tan2).sqrt())
+}
+
+// Density of visible normals, D(h) * G1(wo) * max(0, wo.h) / wo.z
+pub fn ggx_vndf_pdf(wo: Vec3, h: Vec3, alpha_x: f32, alpha_y: f32) -> f32 {
+    let g1 = 1.0 / (1.0 + smith_lambda(wo, alpha_x, alpha_y));
+    ggx_d(h, alpha_x, alpha_y) * g1 * wo.dot(h).max(0.0) / wo.z
+}
+
+// Visible normal sampling (Heitz 2018), returns a microfacet normal in the local frame
+pub fn sample_ggx_vndf(wo: Vec3, alpha_x: f32, alpha_y: f32, u1: f32, u2: f32) -> Vec3 {
+    // stretch the view direction into the hemisphere configuration
+    let vh = Vec3::new(alpha_x * wo.x, alpha_y * wo.y, wo.z).normalize();
+
+    let len_sq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len_sq > 0.0 { Vec3::new(-vh.y, vh.x, 0.0) / len_sq.sqrt() } else { Vec3::X };
+    let t2 = vh.cross(t1);
+
+    // sample the projected area of the visible hemisphere
+    let r = u1.sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+    let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+    // unstretch back to the ellipsoid configuration
+    Vec3::new(alpha_x * nh.x, alpha_y * nh.y, nh.z.max(0.0)).normalize()
+}
+
+// Shading frame from the hit tangents, z is the shading normal
+struct Frame {
+    tangent: Vec3,
+    bitangent: Vec3,
+    normal: Vec3,
+}
+
+impl Frame {
+    fn new(hit: &HitRecord) -> Self {
+        Frame { tangent: hit.tangent, bitangent: hit.bitangent, normal: hit.shading_normal }
+    }
+
+    fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.tangent), v.dot(self.bitangent), v.dot(self.normal))
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        self.tangent * v.x + self.bitangent * v.y + self.normal * v.z
+    }
+}
+
+// Beer-Lambert attenuation for a ray that travelled through the inside of the hit object
+pub fn medium_transmittance(material: &Material, hit: &HitRecord) -> Vec3 {
+    match material.kind {
+        MaterialKind::Dielectric { absorption, .. } if !hit.front_face => (-absorption * hit.t).exp(),
+        _ => Vec3::ONE,
+    }
+}
+
+// Unpolarized fresnel reflectance of a dielectric interface, eta is n_incident / n_transmitted
+// returns 1.0 on total internal reflection
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+
+    let r_s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_s * r_s + r_p * r_p)
+}
+
+// Exact unpolarized fresnel reflectance of a conductor with complex index eta + i k
+pub fn fresnel_conductor(cos_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cos_i * a;
+    let r_s = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let r_p = r_s * (t3 - t4) / (t3 + t4);
+
+    0.5 * (r_s + r_p)
+}
+
+// Start a secondary ray on the side of the surface it is leaving through
+pub fn offset_origin(hit: &HitRecord, direction: Vec3) -> Vec3 {
+    if direction.dot(hit.geometric_normal) >= 0.0 {
+        hit.position + hit.geometric_normal * 0.001
+    } else {
+        hit.position - hit.geometric_normal * 0.001
+    }
+}
+
+pub fn sample_cosine_hemisphere(n: Vec3, rng: &mut ThreadRng) -> Vec3 {
+    // sample disk with sqrt transform
+    let u1: f32 = rng.random();
+    let u2: f32 = rng.random();
+
+    let r = u1.sqrt();
+    let theta = 2.0 * std::f32::consts::PI * u2;
+    let x = r * theta.cos();
+    let y = r * theta.sin();
+    let z = (1.0 - u1).sqrt(); // ensures x^2 + y^2 + z^2 = 1
+
+    // build tangent space
+    let (t, b) = tangent_space(n);
+
+    // world-space direction
+    let dir = (t * x) + (b * y) + (n * z);
+    dir.normalize()
+}
+
+pub fn tangent_space(n: Vec3) -> (Vec3, Vec3) {
+    // choose helper vector to avoid degenerate cross
+    let helper = if n.x.abs() > 0.1 { Vec3::Y } else { Vec3::X };
+    let tangent = n.cross(helper).normalize();
+    let bitangent = n.cross(tangent);
+    (tangent, bitangent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec2;
+
+    // hit on the xy plane at the origin, front_face when the ray travels towards -z
+    fn flat_hit(ray_direction: Vec3) -> HitRecord {
+        HitRecord::new(ray_direction, 1.0, Vec3::ZERO, Vec3::Z, Vec3::Z, Vec2::ZERO, Vec3::X)
+    }
+
+    // Hemisphere integrals of bsdf * cos and of the pdf by midpoint quadrature in (cos theta, phi)
+    fn integrate(material: &Material, ray_direction: Vec3) -> (Vec3, f32) {
+        let hit = flat_hit(ray_direction);
+        let steps = 400;
+        let cell = 2.0 * std::f32::consts::PI / (steps * steps) as f32;
+        let (mut value, mut pdf) = (Vec3::ZERO, 0.0);
+        for i in 0..steps {
+            let cos_theta = (i as f32 + 0.5) / steps as f32;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..steps {
+                let phi = (j as f32 + 0.5) / steps as f32 * 2.0 * std::f32::consts::PI;
+                let wi = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                let eval = eval(material, &hit, Vec3::splat(0.5), ray_direction, wi);
+                value += eval.value() * cell;
+                pdf += eval.pdf * cell;
+            }
+        }
+        (value, pdf)
+    }
+
+    // Average sample weight, the same integral estimated with the sampling routine
+    fn sampled(material: &Material, ray_direction: Vec3) -> Vec3 {
+        let hit = flat_hit(ray_direction);
+        let rng = &mut rand::rng();
+        let n = 200_000;
+        let mut total = Vec3::ZERO;
+        for _ in 0..n {
+            if let Some(sample) = sample(material, &hit, Vec3::splat(0.5), ray_direction, rng) {
+                let eval = eval(material, &hit, Vec3::splat(0.5), ray_direction, sample.direction);
+                assert!((sample.pdf - eval.pdf).abs() <= 1e-3 * eval.pdf, "{} {}", sample.pdf, eval.pdf);
+                total += sample.weight;
+            }
+        }
+        total / n as f32
+    }
+
+    fn assert_sampling_matches(material: &Material, ray_direction: Vec3) {
+        let (integral, pdf) = integrate(material, ray_direction);
+        let estimate = sampled(material, ray_direction);
+        // samples below the horizon are dropped, so the pdf may integrate to less than one
+        assert!(pdf <= 1.01 && pdf > 0.8, "{pdf}");
+        assert!((integral - estimate).abs().max_element() < 0.01, "{integral} {estimate}");
+    }
+
+    #[test]
+    fn power_heuristic_weights() {
+        // two strategies that could both produce a sample share it
+        for (a, b) in [(1.0, 1.0), (0.3, 2.0), (1e-3, 50.0)] {
+            assert!((power_heuristic(a, b) + power_heuristic(b, a) - 1.0).abs() < 1e-6);
+        }
+        assert_eq!(power_heuristic(1.0, 1.0), 0.5);
+        assert!((power_heuristic(2.0, 1.0) - 0.8).abs() < 1e-6);
+        // delta lights keep their full weight, impossible samples get none
+        assert_eq!(power_heuristic(f32::INFINITY, 3.0), 1.0);
+        assert_eq!(power_heuristic(0.0, 3.0), 0.0);
+        // very peaked lobes must not overflow the squares
+        assert!((power_heuristic(1e30, 1e-30) - 1.0).abs() < 1e-6);
+        assert!(power_heuristic(1e-30, 1e30) < 1e-6);
+    }
+
+    #[test]
+    fn ggx_sampling_matches_eval() {
+        // the normal distribution covers the projected area of the surface exactly once
+        let alpha = 0.3;
+        let steps = 1000;
+        let mut projected = 0.0;
+        for i in 0..steps {
+            let cos_theta = (i as f32 + 0.5) / steps as f32;
+            let h = Vec3::new((1.0 - cos_theta * cos_theta).sqrt(), 0.0, cos_theta);
+            projected += ggx_d(h, alpha, alpha) * cos_theta * 2.0 * std::f32::consts::PI / steps as f32;
+        }
+        assert!((projected - 1.0).abs() < 1e-3, "{projected}");
+
+        // a white metal keeps only the GGX lobe
+        let metal = Material { color: Vec3::splat(255.0), metallic: 1.0, roughness: 0.6, ..Default::default() };
+        let grazing = Vec3::new(0.6, 0.3, -0.5).normalize();
+        assert_sampling_matches(&metal, Vec3::NEG_Z);
+        assert_sampling_matches(&metal, grazing);
+        assert_sampling_matches(&Material { anisotropic: 0.8, ..metal }, grazing);
+    }
+
+    #[test]
+    fn principled_lobes_sample_and_reciprocate() {
+        let layered = Material {
+            color: Vec3::new(200.0, 120.0, 60.0),
+            roughness: 0.5,
+            metallic: 0.3,
+            sheen: 0.5,
+            clearcoat: 0.7,
+            clearcoat_roughness: 0.2,
+            subsurface: 0.4,
+            ..Default::default()
+        };
+        assert_sampling_matches(&layered, Vec3::NEG_Z);
+        assert_sampling_matches(&layered, Vec3::new(-0.5, 0.4, -0.6).normalize());
+
+        // swapping the directions gives the same bsdf, cos is taken against the light direction
+        let a = Vec3::new(0.3, -0.5, 0.8).normalize();
+        let b = Vec3::new(-0.6, 0.1, 0.4).normalize();
+        let forward = eval(&layered, &flat_hit(-a), Vec3::splat(0.5), -a, b).value() / b.z;
+        let backward = eval(&layered, &flat_hit(-b), Vec3::splat(0.5), -b, a).value() / a.z;
+        assert!((forward - backward).abs().max_element() < 1e-4, "{forward} {backward}");
+
+        // a white rough dielectric reflects about what arrives, Burley's retro reflection with the
+        // specular layer on top gains a few percent head on
+        let white = Material { color: Vec3::splat(255.0), roughness: 1.0, ..Default::default() };
+        let hit = flat_hit(Vec3::NEG_Z);
+        let rng = &mut rand::rng();
+        let n = 50_000;
+        let total: Vec3 = (0..n).filter_map(|_| sample(&white, &hit, Vec3::ONE, Vec3::NEG_Z, rng)).map(|s| s.weight).sum();
+        let albedo = total / n as f32;
+        assert!(albedo.min_element() > 0.95 && albedo.max_element() < 1.1, "{albedo}");
+    }
+
+    #[test]
+    fn conductor_fresnel_and_presets() {
+        // normal incidence has the closed form ((n - 1)^2 + k^2) / ((n + 1)^2 + k^2), grazing reflects everything
+        let (eta, k) = (0.2f32, 3.9f32);
+        let expected = ((eta - 1.0).powi(2) + k * k) / ((eta + 1.0).powi(2) + k * k);
+        assert!((fresnel_conductor(1.0, eta, k) - expected).abs() < 1e-5);
+        assert!(fresnel_conductor(1e-4, eta, k) > 0.99);
+        // with k = 0 it has to agree with the dielectric formula
+        assert!((fresnel_conductor(0.6, 1.5, 0.0) - fresnel_dielectric(0.6, 1.0 / 1.5)).abs() < 1e-5);
+
+        // the artist friendly mapping reproduces the requested color head on
+        let reflectance = Vec3::new(0.9, 0.6, 0.3);
+        let MaterialKind::Conductor { eta, k } = MaterialKind::conductor_from_reflectance(reflectance, Vec3::ONE) else { unreachable!() };
+        for i in 0..3 {
+            assert!((fresnel_conductor(1.0, eta[i], k[i]) - reflectance[i]).abs() < 1e-3, "{eta} {k}");
+        }
+
+        // gold reflects red more than blue
+        let MaterialKind::Conductor { eta, k } = MaterialKind::GOLD else { unreachable!() };
+        assert!(fresnel_conductor(1.0, eta.x, k.x) > fresnel_conductor(1.0, eta.z, k.z) + 0.3);
+
+        let gold = Material { kind: MaterialKind::GOLD, roughness: 0.5, ..Default::default() };
+        assert_sampling_matches(&gold, Vec3::new(0.3, -0.2, -0.9).normalize());
+    }
+
+    #[test]
+    fn dielectric_fresnel_and_refraction() {
+        // 4% at normal incidence for ior 1.5 in either direction, total internal reflection past the critical angle
+        assert!((fresnel_dielectric(1.0, 1.0 / 1.5) - 0.04).abs() < 1e-6);
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-6);
+        let critical = (1.0f32 / 1.5).asin();
+        assert_eq!(fresnel_dielectric((critical + 0.01).cos(), 1.5), 1.0);
+        assert!(fresnel_dielectric((critical - 0.01).cos(), 1.5) < 1.0);
+
+        let glass = Material { kind: MaterialKind::Dielectric { ior: 1.5, absorption: Vec3::ZERO }, ..Default::default() };
+        let incoming = Vec3::new(0.5, 0.0, -(0.75f32).sqrt());
+        let hit = flat_hit(incoming);
+        let rng = &mut rand::rng();
+        let n = 20_000;
+        let mut reflected = 0;
+        for _ in 0..n {
+            let sample = sample(&glass, &hit, Vec3::ONE, incoming, rng).unwrap();
+            assert!(sample.specular);
+            if sample.direction.z > 0.0 {
+                reflected += 1;
+                assert!((sample.direction - Vec3::new(0.5, 0.0, (0.75f32).sqrt())).length() < 1e-5);
+            } else {
+                // Snell: sin 30 degrees = 1.5 sin theta_t
+                assert!((sample.direction.x - 0.5 / 1.5).abs() < 1e-5, "{}", sample.direction);
+            }
+        }
+        let fresnel = fresnel_dielectric((0.75f32).sqrt(), 1.0 / 1.5);
+        assert!((reflected as f32 / n as f32 - fresnel).abs() < 0.01, "{reflected}");
+
+        // delta lobes have nothing for light sampling to find
+        assert_eq!(eval(&glass, &hit, Vec3::ONE, incoming, Vec3::Z).value(), Vec3::ZERO);
+    }
+
+    #[test]
+    fn absorption_only_inside() {
+        let glass = Material { kind: MaterialKind::Dielectric { ior: 1.5, absorption: Vec3::new(1.0, 0.0, 2.0) }, ..Default::default() };
+        let entering = HitRecord { t: 2.0, ..flat_hit(Vec3::NEG_Z) };
+        let leaving = HitRecord { t: 2.0, ..flat_hit(Vec3::Z) };
+        assert_eq!(medium_transmittance(&glass, &entering), Vec3::ONE);
+        let expected = Vec3::new((-2.0f32).exp(), 1.0, (-4.0f32).exp());
+        assert!((medium_transmittance(&glass, &leaving) - expected).length() < 1e-6);
+    }
+}
@@ -0,0 +1,384 @@
+use glam::Vec3;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub const EMPTY: Aabb = Aabb { min: Vec3::INFINITY, max: Vec3::NEG_INFINITY };
+
+    pub fn from_points(points: &[Vec3]) -> Aabb {
+        points.iter().fold(Aabb::EMPTY, |bounds, &p| bounds.grow(p))
+    }
+
+    pub fn grow(self, p: Vec3) -> Aabb {
+        Aabb { min: self.min.min(p), max: self.max.max(p) }
+    }
+
+    pub fn union(self, other: Aabb) -> Aabb {
+        Aabb { min: self.min.min(other.min), max: self.max.max(other.max) }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let extent = (self.max - self.min).max(Vec3::ZERO);
+        2.0 * (extent.x * extent.y + extent.y * extent.z + extent.z * extent.x)
+    }
+
+    // Slab test, returns the entry distance when the ray reaches the box before t_max
+    pub fn hit(&self, ray_origin: Vec3, inv_dir: Vec3, t_max: f32) -> Option<f32> {
+        let t1 = (self.min - ray_origin) * inv_dir;
+        let t2 = (self.max - ray_origin) * inv_dir;
+
+        let t_enter = t1.min(t2).max_element().max(0.0);
+        let t_exit = t1.max(t2).min_element().min(t_max);
+
+        if t_enter <= t_exit { Some(t_enter) } else { None }
+    }
+}
+
+struct BvhNode {
+    bounds: Aabb,
+    // leaves point into item_indices, interior nodes to their left child (right is left + 1)
+    first: u32,
+    count: u32,
+}
+
+// Bounding volume hierarchy over anything that has a box, built with the surface area heuristic
+// Items are referred to by their index in the slice the tree was built from
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    item_indices: Vec<u32>,
+}
+
+const SAH_BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+// deeper nodes stay leaves, uneven splits of badly spread items would otherwise recurse once per item
+const MAX_DEPTH: usize = 64;
+// relative cost of a box test against an item test
+const TRAVERSAL_COST: f32 = 0.5;
+
+impl Bvh {
+    pub fn build(bounds: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(bounds.len() * 2),
+            item_indices: (0..bounds.len() as u32).collect(),
+        };
+
+        let centroids: Vec<Vec3> = bounds.iter().map(Aabb::centroid).collect();
+        bvh.nodes.push(BvhNode { bounds: Aabb::EMPTY, first: 0, count: bounds.len() as u32 });
+        if !bounds.is_empty() {
+            bvh.subdivide(0, bounds, &centroids, 0);
+        }
+        bvh
+    }
+
+    fn subdivide(&mut self, node_index: usize, bounds: &[Aabb], centroids: &[Vec3], depth: usize) {
+        let first = self.nodes[node_index].first as usize;
+        let count = self.nodes[node_index].count as usize;
+        let items = &self.item_indices[first..first + count];
+
+        let node_bounds = items.iter().fold(Aabb::EMPTY, |b, &i| b.union(bounds[i as usize]));
+        let centroid_bounds = items.iter().fold(Aabb::EMPTY, |b, &i| b.grow(centroids[i as usize]));
+        self.nodes[node_index].bounds = node_bounds;
+
+        if count <= MAX_LEAF_SIZE || depth >= MAX_DEPTH {
+            return;
+        }
+
+        let Some((axis, split)) = find_split(items, bounds, centroids, centroid_bounds, node_bounds) else {
+            return;
+        };
+
+        // partition the items in place around the chosen bin boundary
+        let items = &mut self.item_indices[first..first + count];
+        let mut left_count = 0;
+        for i in 0..count {
+            if centroids[items[i] as usize][axis] < split {
+                items.swap(i, left_count);
+                left_count += 1;
+            }
+        }
+
+        if left_count == 0 || left_count == count {
+            return;
+        }
+
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode { bounds: Aabb::EMPTY, first: first as u32, count: left_count as u32 });
+        self.nodes.push(BvhNode { bounds: Aabb::EMPTY, first: (first + left_count) as u32, count: (count - left_count) as u32 });
+
+        self.nodes[node_index].first = left as u32;
+        self.nodes[node_index].count = 0;
+
+        self.subdivide(left, bounds, centroids, depth + 1);
+        self.subdivide(left + 1, bounds, centroids, depth + 1);
+    }
+
+    // Calls hit for every item whose box the ray reaches before the closest hit so far
+    // hit returns the distance of an intersection with that item, near children are visited first
+    pub fn traverse<F>(&self, ray_origin: Vec3, ray_dir: Vec3, mut t_max: f32, mut hit: F)
+    where
+        F: FnMut(usize) -> Option<f32>,
+    {
+        if self.item_indices.is_empty() {
+            return;
+        }
+
+        let inv_dir = ray_dir.recip();
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
+
+        if self.nodes[0].bounds.hit(ray_origin, inv_dir, t_max).is_none() {
+            return;
+        }
+        let mut node_index = 0;
+
+        loop {
+            let node = &self.nodes[node_index];
+
+            if node.count > 0 {
+                let first = node.first as usize;
+                for &item in self.item_indices[first..first + node.count as usize].iter() {
+                    if let Some(t) = hit(item as usize) {
+                        t_max = t_max.min(t);
+                    }
+                }
+            } else {
+                let left = node.first as usize;
+                let t_left = self.nodes[left].bounds.hit(ray_origin, inv_dir, t_max);
+                let t_right = self.nodes[left + 1].bounds.hit(ray_origin, inv_dir, t_max);
+
+                match (t_left, t_right) {
+                    (Some(tl), Some(tr)) => {
+                        let (near, far) = if tl <= tr { (left, left + 1) } else { (left + 1, left) };
+                        stack.push(far);
+                        node_index = near;
+                        continue;
+                    }
+                    (Some(_), None) => { node_index = left; continue; }
+                    (None, Some(_)) => { node_index = left + 1; continue; }
+                    (None, None) => {}
+                }
+            }
+
+            // pop the next node that is still in front of the closest hit
+            loop {
+                let Some(next) = stack.pop() else {
+                    return;
+                };
+                if self.nodes[next].bounds.hit(ray_origin, inv_dir, t_max).is_some() {
+                    node_index = next;
+                    break;
+                }
+            }
+        }
+    }
+
+    // Returns as soon as hit reports an intersection, order does not matter for shadow rays
+    pub fn any_hit<F>(&self, ray_origin: Vec3, ray_dir: Vec3, t_max: f32, mut hit: F) -> bool
+    where
+        F: FnMut(usize) -> bool,
+    {
+        if self.item_indices.is_empty() {
+            return false;
+        }
+
+        let inv_dir = ray_dir.recip();
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
+        stack.push(0);
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+
+            if node.bounds.hit(ray_origin, inv_dir, t_max).is_none() {
+                continue;
+            }
+
+            if node.count > 0 {
+                let first = node.first as usize;
+                for &item in self.item_indices[first..first + node.count as usize].iter() {
+                    if hit(item as usize) {
+                        return true;
+                    }
+                }
+            } else {
+                stack.push(node.first as usize);
+                stack.push(node.first as usize + 1);
+            }
+        }
+
+        false
+    }
+}
+
+// Binned SAH, returns the axis and centroid coordinate to split at,
+// or None when keeping the node as a leaf is cheaper
+fn find_split(
+    items: &[u32],
+    bounds: &[Aabb],
+    centroids: &[Vec3],
+    centroid_bounds: Aabb,
+    node_bounds: Aabb
+) -> Option<(usize, f32)> {
+    let mut best: Option<(usize, f32, f32)> = None;
+
+    for axis in [0, 1, 2] {
+        let lo = centroid_bounds.min[axis];
+        let hi = centroid_bounds.max[axis];
+        if hi - lo <= 1e-6 {
+            continue;
+        }
+
+        let mut bin_bounds = [Aabb::EMPTY; SAH_BINS];
+        let mut bin_counts = [0usize; SAH_BINS];
+        let scale = SAH_BINS as f32 / (hi - lo);
+
+        for &i in items.iter() {
+            let bin = (((centroids[i as usize][axis] - lo) * scale) as usize).min(SAH_BINS - 1);
+            bin_bounds[bin] = bin_bounds[bin].union(bounds[i as usize]);
+            bin_counts[bin] += 1;
+        }
+
+        // sweep from the right to get the cost of every right hand side
+        let mut right_area = [0.0f32; SAH_BINS];
+        let mut right_count = [0usize; SAH_BINS];
+        let mut acc_bounds = Aabb::EMPTY;
+        let mut acc_count = 0;
+        for b in (1..SAH_BINS).rev() {
+            acc_bounds = acc_bounds.union(bin_bounds[b]);
+            acc_count += bin_counts[b];
+            right_area[b] = acc_bounds.surface_area();
+            right_count[b] = acc_count;
+        }
+
+        let mut left_bounds = Aabb::EMPTY;
+        let mut left_count = 0;
+        for b in 1..SAH_BINS {
+            left_bounds = left_bounds.union(bin_bounds[b - 1]);
+            left_count += bin_counts[b - 1];
+            if left_count == 0 || right_count[b] == 0 {
+                continue;
+            }
+
+            let cost = left_count as f32 * left_bounds.surface_area() + right_count[b] as f32 * right_area[b];
+            if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                best = Some((axis, lo + b as f32 / scale, cost));
+            }
+        }
+    }
+
+    let (axis, split, cost) = best?;
+
+    let leaf_cost = items.len() as f32;
+    let split_cost = TRAVERSAL_COST + cost / node_bounds.surface_area().max(1e-12);
+    if split_cost >= leaf_cost && items.len() <= 16 {
+        return None;
+    }
+
+    Some((axis, split))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+
+    fn random_boxes(rng: &mut StdRng, count: usize) -> Vec<Aabb> {
+        (0..count)
+            .map(|_| {
+                let center = Vec3::new(rng.random(), rng.random(), rng.random()) * 10.0 - 5.0;
+                let half_size = Vec3::new(rng.random(), rng.random(), rng.random()) * 0.4 + 0.01;
+                Aabb { min: center - half_size, max: center + half_size }
+            })
+            .collect()
+    }
+
+    fn random_ray(rng: &mut StdRng) -> (Vec3, Vec3) {
+        let origin = Vec3::new(rng.random(), rng.random(), rng.random()) * 16.0 - 8.0;
+        let target = Vec3::new(rng.random(), rng.random(), rng.random()) * 8.0 - 4.0;
+        (origin, (target - origin).normalize())
+    }
+
+    // the items are the boxes themselves, hit where the ray enters them
+    fn closest_hit(bvh: &Bvh, boxes: &[Aabb], origin: Vec3, direction: Vec3) -> Option<(usize, f32)> {
+        let mut closest: Option<(usize, f32)> = None;
+        bvh.traverse(origin, direction, f32::INFINITY, |i| {
+            let t = boxes[i].hit(origin, direction.recip(), f32::INFINITY)?;
+            if closest.is_none_or(|(_, closest_t)| t < closest_t) {
+                closest = Some((i, t));
+            }
+            Some(t)
+        });
+        closest
+    }
+
+    fn brute_force(boxes: &[Aabb], origin: Vec3, direction: Vec3) -> Option<(usize, f32)> {
+        boxes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, b)| Some((i, b.hit(origin, direction.recip(), f32::INFINITY)?)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    fn depth(bvh: &Bvh, node: usize) -> usize {
+        match bvh.nodes[node].count {
+            0 => 1 + depth(bvh, bvh.nodes[node].first as usize).max(depth(bvh, bvh.nodes[node].first as usize + 1)),
+            _ => 0,
+        }
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let rng = &mut StdRng::seed_from_u64(7);
+        for count in [0, 1, 3, 50, 1000] {
+            let boxes = random_boxes(rng, count);
+            let bvh = Bvh::build(&boxes);
+
+            for _ in 0..500 {
+                let (origin, direction) = random_ray(rng);
+                let expected = brute_force(&boxes, origin, direction);
+                let found = closest_hit(&bvh, &boxes, origin, direction);
+                assert_eq!(found.map(|(_, t)| t), expected.map(|(_, t)| t));
+
+                // shadow rays only care whether anything is closer than the limit
+                let limit = rng.random::<f32>() * 12.0;
+                let blocked = bvh.any_hit(origin, direction, limit, |i| boxes[i].hit(origin, direction.recip(), limit).is_some());
+                assert_eq!(blocked, expected.is_some_and(|(_, t)| t <= limit));
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate_items() {
+        // every item at the same spot can never be split
+        let same = vec![Aabb { min: Vec3::ZERO, max: Vec3::ONE }; 500];
+        let bvh = Bvh::build(&same);
+        let mut visited = 0;
+        bvh.traverse(Vec3::new(0.5, 0.5, -1.0), Vec3::Z, f32::INFINITY, |_| {
+            visited += 1;
+            None
+        });
+        assert_eq!(visited, same.len());
+
+        // doubling distances peel a single item off per split
+        let spread: Vec<Aabb> = (0..120)
+            .map(|i| {
+                let x = 2.0f32.powi(i);
+                Aabb { min: Vec3::new(x, 0.0, 0.0), max: Vec3::new(x, 1.0, 1.0) }
+            })
+            .collect();
+        let bvh = Bvh::build(&spread);
+        assert!(depth(&bvh, 0) <= MAX_DEPTH);
+        let mut found = vec![false; spread.len()];
+        bvh.traverse(Vec3::new(-1.0, 0.5, 0.5), Vec3::X, f32::INFINITY, |i| {
+            found[i] = true;
+            None
+        });
+        assert!(found.iter().all(|&f| f));
+    }
+}
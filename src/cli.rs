@@ -0,0 +1,377 @@
+use std::path::PathBuf;
+
+use crate::output::ExrPixelType;
+use crate::tonemap::{DisplayTransform, ToneMap};
+
+pub const USAGE: &str = "\
+usage: RAY_TRACER [options]
+
+  --scene <file>               .obj, .gltf, .glb, .ply or .stl to render instead of the demo scene
+  --width <pixels>             image width up to 16384, default 800
+  --height <pixels>            image height up to 16384, default 800
+  --spp <samples>              samples per pixel, default 150
+  --depth <bounces>            maximum bounces per path, default 6
+  --projection <type>          perspective, ortho, fisheye, equirect, stereo or stereo-equirect, default the camera's
+  --fov <degrees>              vertical field of view, or the image circle of the fisheye
+  --ortho-height <size>        height of the orthographic view, default the perspective framing at the focus distance
+  --eye-separation <distance>  distance between the stereo eyes, default 0.065
+  --aperture <radius>          lens radius for depth of field, default 0 (pinhole)
+  --focus <distance>           distance to the sharp plane, default the camera's
+  --blades <count>             polygonal aperture with this many blades instead of a circle
+  --blade-rotation <degrees>   rotation of the polygonal aperture, default 0
+  --aperture-mask <file>       .png whose brightness shapes the aperture
+  --env <file>                 .hdr environment map lighting the scene, replaces the default sky
+  --env-intensity <scale>      brightness of the environment map, default 1
+  --env-rotation <degrees>     rotation of the environment map around the up axis, default 0
+  --sun-elevation <degrees>    height of the sun above the horizon for the sky, default 45
+  --sun-azimuth <degrees>      direction of the sun clockwise from north (-z), default 135
+  --latitude <degrees>         place on earth to put the sun by date and time instead, north positive, default 0
+  --longitude <degrees>        east positive, default 0
+  --day <day>                  day of the year from 1 to 366, default 172
+  --time <hours>               time of day in hours UTC, default 12
+  --turbidity <haze>           haziness of the sky from 1.7 (clear) to 10 (hazy), default 3
+  --sky-intensity <scale>      brightness of the sky, default 1
+  --output <file>              render headless into a .exr, .hdr, .png or .ppm file instead of opening a window
+  --exr-type <type>            half or float channels in .exr files, default half
+  --aovs                       add albedo, normal, position, depth, id and light passes to .exr files
+  --denoise                    smooth the noise away guided by the albedo, normal and depth passes
+  --tonemap <curve>            clamp, reinhard, aces or agx for the window, .png and .ppm, default clamp
+  --exposure <stops>           brightness change before tone mapping, default 0
+  --help                       show this text";
+
+const MAX_IMAGE_SIZE: usize = 16384;
+
+// Camera projection picked on the command line, the parameters come from the other camera options
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProjectionKind {
+    Perspective,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+    Stereo,
+    StereoEquirectangular,
+}
+
+impl ProjectionKind {
+    // perspective views reach infinity at 180 degrees, only the fisheye goes on up to a full circle
+    pub fn accepts_fov(self, fov: f32) -> bool {
+        match self {
+            ProjectionKind::Perspective | ProjectionKind::Stereo => fov < 180.0,
+            _ => fov <= 360.0,
+        }
+    }
+}
+
+// Command line settings, without an output file the image is shown in a window
+pub struct Options {
+    pub scene: Option<PathBuf>,
+    pub width: usize,
+    pub height: usize,
+    pub samples: u32,
+    pub max_depth: u32,
+    // camera overrides, None keeps what the demo or the scene file set up
+    pub projection: Option<ProjectionKind>,
+    pub fov: Option<f32>,
+    pub ortho_height: Option<f32>,
+    pub eye_separation: f32,
+    pub aperture_radius: Option<f32>,
+    pub focus_distance: Option<f32>,
+    pub blades: Option<u32>,
+    pub blade_rotation: f32,
+    pub aperture_mask: Option<PathBuf>,
+    pub environment_map: Option<PathBuf>,
+    pub env_intensity: f32,
+    pub env_rotation: f32,
+    // sky settings, any of them lights the demo and scenes with their own lights by the sky as well
+    pub sun_elevation: Option<f32>,
+    pub sun_azimuth: Option<f32>,
+    pub latitude: Option<f32>,
+    pub longitude: Option<f32>,
+    pub day_of_year: Option<u32>,
+    pub utc_time: Option<f32>,
+    pub turbidity: Option<f32>,
+    pub sky_intensity: Option<f32>,
+    pub output: Option<PathBuf>,
+    pub exr_pixel_type: ExrPixelType,
+    // render the extra passes, only .exr files can hold them
+    pub aovs: bool,
+    pub denoise: bool,
+    pub display: DisplayTransform,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            scene: None,
+            width: 800,
+            height: 800,
+            samples: 150,
+            max_depth: 6,
+            projection: None,
+            fov: None,
+            ortho_height: None,
+            eye_separation: 0.065,
+            aperture_radius: None,
+            focus_distance: None,
+            blades: None,
+            blade_rotation: 0.0,
+            aperture_mask: None,
+            environment_map: None,
+            env_intensity: 1.0,
+            env_rotation: 0.0,
+            sun_elevation: None,
+            sun_azimuth: None,
+            latitude: None,
+            longitude: None,
+            day_of_year: None,
+            utc_time: None,
+            turbidity: None,
+            sky_intensity: None,
+            output: None,
+            exr_pixel_type: ExrPixelType::Half,
+            aovs: false,
+            denoise: false,
+            display: DisplayTransform::default(),
+            help: false,
+        }
+    }
+}
+
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        // accept both "--flag value" and "--flag=value"
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg, None),
+        };
+        let mut value = || inline_value.clone().or_else(|| args.next()).ok_or_else(|| format!("missing value for {flag}"));
+
+        match flag.as_str() {
+            "--scene" => options.scene = Some(PathBuf::from(value()?)),
+            "--width" => options.width = parse_size(&flag, &value()?)?,
+            "--height" => options.height = parse_size(&flag, &value()?)?,
+            "--spp" => options.samples = parse_number(&flag, &value()?)?,
+            "--depth" => options.max_depth = value()?.parse().map_err(|_| format!("invalid value for {flag}"))?,
+            "--projection" => {
+                options.projection = Some(match value()?.as_str() {
+                    "perspective" => ProjectionKind::Perspective,
+                    "ortho" => ProjectionKind::Orthographic,
+                    "fisheye" => ProjectionKind::Fisheye,
+                    "equirect" => ProjectionKind::Equirectangular,
+                    "stereo" => ProjectionKind::Stereo,
+                    "stereo-equirect" => ProjectionKind::StereoEquirectangular,
+                    other => {
+                        return Err(format!(
+                            "--projection expects perspective, ortho, fisheye, equirect, stereo or stereo-equirect, got {other}"
+                        ))
+                    }
+                })
+            }
+            "--fov" => {
+                let value = value()?;
+                match value.parse::<f32>() {
+                    Ok(fov) if fov > 0.0 && fov <= 360.0 => options.fov = Some(fov),
+                    _ => return Err(format!("{flag} expects an angle above 0 and up to 360 degrees, got {value}")),
+                }
+            }
+            "--ortho-height" => options.ortho_height = Some(parse_distance(&flag, &value()?)?),
+            "--eye-separation" => options.eye_separation = parse_distance(&flag, &value()?)?,
+            "--aperture" => options.aperture_radius = Some(parse_distance(&flag, &value()?)?),
+            "--focus" => options.focus_distance = Some(parse_positive(&flag, &value()?)?),
+            "--blades" => {
+                let blades: u32 = parse_number(&flag, &value()?)?;
+                if blades < 3 {
+                    return Err(format!("{flag} expects at least 3 blades, got {blades}"));
+                }
+                options.blades = Some(blades);
+            }
+            "--blade-rotation" => {
+                options.blade_rotation = value()?.parse().map_err(|_| format!("invalid value for {flag}"))?
+            }
+            "--aperture-mask" => options.aperture_mask = Some(PathBuf::from(value()?)),
+            "--env" => options.environment_map = Some(PathBuf::from(value()?)),
+            "--env-intensity" => options.env_intensity = parse_scale(&flag, &value()?)?,
+            "--env-rotation" => {
+                options.env_rotation = value()?.parse().map_err(|_| format!("invalid value for {flag}"))?
+            }
+            "--sun-elevation" => options.sun_elevation = Some(parse_range(&flag, &value()?, -90.0, 90.0)?),
+            "--sun-azimuth" => options.sun_azimuth = Some(parse_range(&flag, &value()?, -360.0, 360.0)?),
+            "--latitude" => options.latitude = Some(parse_range(&flag, &value()?, -90.0, 90.0)?),
+            "--longitude" => options.longitude = Some(parse_range(&flag, &value()?, -180.0, 180.0)?),
+            "--day" => {
+                let value = value()?;
+                match value.parse::<u32>() {
+                    Ok(day) if (1..=366).contains(&day) => options.day_of_year = Some(day),
+                    _ => return Err(format!("{flag} expects a day of the year from 1 to 366, got {value}")),
+                }
+            }
+            "--time" => options.utc_time = Some(parse_range(&flag, &value()?, 0.0, 24.0)?),
+            "--turbidity" => options.turbidity = Some(parse_range(&flag, &value()?, 1.7, 10.0)?),
+            "--sky-intensity" => options.sky_intensity = Some(parse_scale(&flag, &value()?)?),
+            "--output" => options.output = Some(PathBuf::from(value()?)),
+            "--exr-type" => {
+                options.exr_pixel_type = match value()?.as_str() {
+                    "half" => ExrPixelType::Half,
+                    "float" => ExrPixelType::Float,
+                    other => return Err(format!("--exr-type expects half or float, got {other}")),
+                }
+            }
+            "--tonemap" => {
+                options.display.tone_map = match value()?.as_str() {
+                    "clamp" => ToneMap::Clamp,
+                    "reinhard" => ToneMap::Reinhard,
+                    "aces" => ToneMap::Aces,
+                    "agx" => ToneMap::Agx,
+                    other => return Err(format!("--tonemap expects clamp, reinhard, aces or agx, got {other}")),
+                }
+            }
+            "--exposure" => {
+                options.display.exposure = value()?.parse().map_err(|_| format!("invalid value for {flag}"))?
+            }
+            "--aovs" => options.aovs = true,
+            "--denoise" => options.denoise = true,
+            "--help" | "-h" => options.help = true,
+            _ => return Err(format!("unknown argument {flag}")),
+        }
+    }
+
+    if let (Some(fov), Some(projection)) = (options.fov, options.projection) {
+        if !projection.accepts_fov(fov) {
+            return Err(format!("--fov has to stay below 180 degrees for the perspective and stereo projections, got {fov}"));
+        }
+    }
+
+    Ok(options)
+}
+
+// positive whole numbers only, a zero sized image or zero samples is a mistake
+fn parse_number<T: std::str::FromStr + Default + PartialEq>(flag: &str, value: &str) -> Result<T, String> {
+    match value.parse::<T>() {
+        Ok(number) if number != T::default() => Ok(number),
+        _ => Err(format!("{flag} expects a positive number, got {value}")),
+    }
+}
+
+// image sides up to 16384 pixels, which keeps the rgba buffer of the window within an i32
+fn parse_size(flag: &str, value: &str) -> Result<usize, String> {
+    let size: usize = parse_number(flag, value)?;
+    if size > MAX_IMAGE_SIZE {
+        return Err(format!("{flag} expects at most {MAX_IMAGE_SIZE} pixels, got {value}"));
+    }
+    Ok(size)
+}
+
+// finite and not negative, for lengths in scene units
+fn parse_distance(flag: &str, value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(distance) if distance.is_finite() && distance >= 0.0 => Ok(distance),
+        _ => Err(format!("{flag} expects a distance of 0 or more, got {value}")),
+    }
+}
+
+// finite and above 0, for distances the camera divides by or aims at
+fn parse_positive(flag: &str, value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(distance) if distance.is_finite() && distance > 0.0 => Ok(distance),
+        _ => Err(format!("{flag} expects a distance above 0, got {value}")),
+    }
+}
+
+// finite and not negative, for brightness multipliers
+fn parse_scale(flag: &str, value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(scale) if scale.is_finite() && scale >= 0.0 => Ok(scale),
+        _ => Err(format!("{flag} expects a value of 0 or more, got {value}")),
+    }
+}
+
+// inclusive bounds, for angles and other values with a natural range
+fn parse_range(flag: &str, value: &str, min: f32, max: f32) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(number) if (min..=max).contains(&number) => Ok(number),
+        _ => Err(format!("{flag} expects a value from {min} to {max}, got {value}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn inline_and_separate_values() {
+        let options = parse(&["--width=640", "--height", "360", "--output=out.png", "--tonemap=aces", "--aovs"]).unwrap();
+        assert_eq!((options.width, options.height), (640, 360));
+        assert_eq!(options.output, Some(PathBuf::from("out.png")));
+        assert!(matches!(options.display.tone_map, ToneMap::Aces));
+        assert!(options.aovs && !options.denoise);
+        // only the first = splits, paths may hold more of them
+        assert_eq!(parse(&["--scene=a=b.obj"]).unwrap().scene, Some(PathBuf::from("a=b.obj")));
+        // and the defaults stay for everything else
+        assert_eq!(options.samples, 150);
+        assert!(options.projection.is_none() && options.fov.is_none());
+    }
+
+    #[test]
+    fn missing_and_unknown_arguments() {
+        assert_eq!(parse(&["--spp"]).err().unwrap(), "missing value for --spp");
+        assert_eq!(parse(&["--width", "100", "--output"]).err().unwrap(), "missing value for --output");
+        assert_eq!(parse(&["--frobnicate"]).err().unwrap(), "unknown argument --frobnicate");
+        assert_eq!(parse(&["--frobnicate=1"]).err().unwrap(), "unknown argument --frobnicate");
+        assert!(parse(&["--projection", "cylinder"]).is_err());
+        assert!(parse(&["--exr-type=double"]).is_err());
+        assert!(parse(&["--help"]).unwrap().help);
+    }
+
+    #[test]
+    fn values_out_of_range() {
+        for args in [
+            ["--width", "0"],
+            ["--height", "16385"],
+            ["--spp", "-3"],
+            ["--spp", "many"],
+            ["--fov", "0"],
+            ["--blades", "2"],
+            ["--aperture", "-0.1"],
+            ["--env-intensity", "nan"],
+            ["--sun-elevation", "91"],
+            ["--longitude", "-181"],
+            ["--day", "367"],
+            ["--time", "24.5"],
+            ["--turbidity", "1"],
+        ] {
+            assert!(parse(&args).is_err(), "{args:?}");
+        }
+        // the bounds themselves are fine
+        let options = parse(&["--width", "16384", "--day", "366", "--turbidity", "10", "--blades", "3", "--depth", "0"]).unwrap();
+        assert_eq!((options.width, options.day_of_year, options.turbidity, options.blades, options.max_depth), (16384, Some(366), Some(10.0), Some(3), 0));
+    }
+
+    #[test]
+    fn fov_depends_on_the_projection() {
+        assert!(parse(&["--projection", "perspective", "--fov", "180"]).is_err());
+        assert!(parse(&["--fov", "200", "--projection", "stereo"]).is_err());
+        assert_eq!(parse(&["--projection", "perspective", "--fov", "179"]).unwrap().fov, Some(179.0));
+        assert_eq!(parse(&["--projection", "fisheye", "--fov", "360"]).unwrap().fov, Some(360.0));
+        assert!(parse(&["--projection", "fisheye", "--fov", "361"]).is_err());
+        // without a projection the camera of the scene decides
+        assert_eq!(parse(&["--fov", "220"]).unwrap().fov, Some(220.0));
+    }
+
+    #[test]
+    fn focus_has_to_be_in_front_of_the_camera() {
+        assert!(parse(&["--focus", "0"]).is_err());
+        assert!(parse(&["--focus", "-1"]).is_err());
+        assert!(parse(&["--focus", "inf"]).is_err());
+        assert_eq!(parse(&["--focus", "0.5"]).unwrap().focus_distance, Some(0.5));
+        // a zero aperture is still a pinhole
+        assert_eq!(parse(&["--aperture", "0"]).unwrap().aperture_radius, Some(0.0));
+    }
+}
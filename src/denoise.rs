@@ -0,0 +1,169 @@
+use glam::{Vec2, Vec3};
+
+use crate::framebuffer::Framebuffer;
+
+// B3 spline, applied along both axes with growing holes between the taps
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+// the footprint doubles every pass, five passes cover 125 pixels across
+const ITERATIONS: u32 = 5;
+
+// how quickly the weights fall off across edges, the color one halves every pass
+// as the noise it has to tolerate goes down
+const SIGMA_COLOR: f32 = 0.5;
+const SIGMA_ALBEDO: f32 = 0.1;
+const NORMAL_POWER: f32 = 64.0;
+const SIGMA_DEPTH: f32 = 1.0;
+// below this the albedo is too dark to divide the texture out of the lighting
+const MIN_ALBEDO: f32 = 1e-3;
+
+// Edge avoiding a-trous wavelet filter (Dammertz et al. 2010) over the color,
+// guided by the albedo, normal and depth layers which have to be rendered along with it.
+// The lighting is filtered with the albedo divided out so that texture detail survives.
+pub fn denoise(image: &mut Framebuffer) -> Result<(), String> {
+    let (width, height) = (image.width, image.height);
+    let layer = |name: &str| {
+        image.layers.iter().find(|layer| layer.name == name).map(|layer| &layer.pixels[..]).ok_or_else(|| format!("denoising needs the {name} layer"))
+    };
+    let albedo = layer("albedo")?;
+    let normal = layer("normal")?;
+    let depth: Vec<f32> = layer("depth")?.iter().map(|d| d.x).collect();
+
+    let demodulate = |a: Vec3| Vec3::select(a.cmpgt(Vec3::splat(MIN_ALBEDO)), a, Vec3::ONE);
+    // a single nan or inf sample would spread over the whole footprint, such pixels start out black
+    let mut lighting: Vec<Vec3> = image
+        .color
+        .iter()
+        .zip(albedo)
+        .map(|(&c, &a)| if c.is_finite() { c / demodulate(a) } else { Vec3::ZERO })
+        .collect();
+    let mut filtered = vec![Vec3::ZERO; lighting.len()];
+    let gradient = depth_gradient(&depth, width, height);
+
+    for iteration in 0..ITERATIONS {
+        let step = 1i32 << iteration;
+        let sigma_color = SIGMA_COLOR / (1 << iteration) as f32;
+
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                let p = y as usize * width + x as usize;
+                let color_p = compress(lighting[p]);
+                let mut sum = Vec3::ZERO;
+                let mut total_weight = 0.0;
+
+                for (j, ky) in KERNEL.iter().enumerate() {
+                    let qy = y + (j as i32 - 2) * step;
+                    if qy < 0 || qy >= height as i32 {
+                        continue;
+                    }
+                    for (i, kx) in KERNEL.iter().enumerate() {
+                        let qx = x + (i as i32 - 2) * step;
+                        if qx < 0 || qx >= width as i32 {
+                            continue;
+                        }
+                        let q = qy as usize * width + qx as usize;
+                        let offset = Vec2::new((qx - x) as f32, (qy - y) as f32);
+
+                        // background only blends with background, surfaces only with surfaces
+                        let geometry = match (depth[p].is_finite(), depth[q].is_finite()) {
+                            (true, true) => {
+                                // a surface at the camera on a flat gradient expects no change at all
+                                let expected = (SIGMA_DEPTH * gradient[p].dot(offset).abs() + 1e-3 * depth[p]).max(1e-6);
+                                let w_depth = (-(depth[p] - depth[q]).abs() / expected).exp();
+                                let w_normal = normal[p].dot(normal[q]).max(0.0).powf(NORMAL_POWER);
+                                let w_albedo = (-(albedo[p] - albedo[q]).length_squared() / (SIGMA_ALBEDO * SIGMA_ALBEDO)).exp();
+                                w_depth * w_normal * w_albedo
+                            }
+                            (false, false) => 1.0,
+                            _ => 0.0,
+                        };
+                        let w_color = (-(color_p - compress(lighting[q])).length_squared() / (sigma_color * sigma_color)).exp();
+
+                        let weight = kx * ky * geometry * w_color;
+                        sum += lighting[q] * weight;
+                        total_weight += weight;
+                    }
+                }
+
+                // the centre tap can still underflow to zero next to a very different neighbourhood
+                filtered[p] = if total_weight > 0.0 { sum / total_weight } else { lighting[p] };
+            }
+        }
+        std::mem::swap(&mut lighting, &mut filtered);
+    }
+
+    image.color = lighting.iter().zip(albedo).map(|(&l, &a)| l * demodulate(a)).collect();
+    Ok(())
+}
+
+// x / (1 + x), so that single very bright samples do not decide the color distance
+fn compress(c: Vec3) -> Vec3 {
+    let c = c.max(Vec3::ZERO);
+    c / (Vec3::ONE + c)
+}
+
+// Change of depth per pixel in screen space, used to tell a slanted surface from a depth jump
+fn depth_gradient(depth: &[f32], width: usize, height: usize) -> Vec<Vec2> {
+    // central difference, one sided next to the background and the image border
+    let derivative = |before: Option<f32>, centre: f32, after: Option<f32>| {
+        match (before.filter(|d| d.is_finite()), after.filter(|d| d.is_finite())) {
+            (Some(before), Some(after)) => (after - before) * 0.5,
+            (Some(before), None) => centre - before,
+            (None, Some(after)) => after - centre,
+            (None, None) => 0.0,
+        }
+    };
+
+    let mut gradient = vec![Vec2::ZERO; depth.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let p = y * width + x;
+            if !depth[p].is_finite() {
+                continue;
+            }
+            let left = (x > 0).then(|| depth[p - 1]);
+            let right = (x + 1 < width).then(|| depth[p + 1]);
+            let up = (y > 0).then(|| depth[p - width]);
+            let down = (y + 1 < height).then(|| depth[p + width]);
+            gradient[p] = Vec2::new(derivative(left, depth[p], right), derivative(up, depth[p], down));
+        }
+    }
+    gradient
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::Layer;
+
+    fn guided_image(width: usize, height: usize, depth: f32) -> Framebuffer {
+        let mut image = Framebuffer::new(width, height);
+        image.color = vec![Vec3::splat(0.5); width * height];
+        let layer = |name: &str, value: Vec3| Layer { name: name.to_string(), channels: &["R", "G", "B"], pixels: vec![value; width * height] };
+        image.layers = vec![layer("albedo", Vec3::splat(0.8)), layer("normal", Vec3::Z), layer("depth", Vec3::splat(depth))];
+        image
+    }
+
+    #[test]
+    fn flat_image_stays_flat() {
+        let mut image = guided_image(8, 8, 2.0);
+        denoise(&mut image).unwrap();
+        assert!(image.color.iter().all(|c| (*c - Vec3::splat(0.5)).abs().max_element() < 1e-5));
+    }
+
+    #[test]
+    fn non_finite_input_stays_contained() {
+        // zero depth used to divide zero by zero
+        let mut image = guided_image(8, 8, 0.0);
+        image.color[9] = Vec3::NAN;
+        image.color[20] = Vec3::INFINITY;
+        denoise(&mut image).unwrap();
+        assert!(image.color.iter().all(|c| c.is_finite()));
+        assert!(image.color[0].x > 0.3);
+    }
+
+    #[test]
+    fn missing_guides() {
+        let mut image = Framebuffer::new(4, 4);
+        assert!(denoise(&mut image).is_err());
+    }
+}
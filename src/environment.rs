@@ -0,0 +1,263 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+use rand::prelude::*;
+
+use crate::bsdf::luminance;
+use crate::loaders::hdr::HdrImage;
+use crate::sky::Sky;
+
+// Whatever surrounds the scene at infinity
+pub enum Environment {
+    Map(EnvironmentMap),
+    Sky(Sky),
+}
+
+impl Environment {
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        match self {
+            Environment::Map(map) => map.radiance(direction),
+            Environment::Sky(sky) => sky.radiance(direction),
+        }
+    }
+
+    pub fn sample(&self, rng: &mut ThreadRng) -> Option<EnvironmentSample> {
+        match self {
+            Environment::Map(map) => map.sample(rng),
+            Environment::Sky(sky) => sky.sample(rng),
+        }
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        match self {
+            Environment::Map(map) => map.pdf(direction),
+            Environment::Sky(sky) => sky.pdf(direction),
+        }
+    }
+}
+
+// Equirectangular image lighting the scene from infinitely far away
+// +y is up and the center of the image looks down -z
+pub struct EnvironmentMap {
+    pub width: usize,
+    pub height: usize,
+    // linear radiance, rows from top to bottom
+    pub pixels: Vec<Vec3>,
+    pub intensity: f32,
+    // rotation around the up axis in degrees
+    pub rotation: f32,
+    // cumulative distributions for picking a row and then a pixel inside it by luminance
+    marginal_cdf: Vec<f32>,
+    conditional_cdf: Vec<f32>,
+}
+
+// Direction towards the environment and the radiance arriving along it
+pub struct EnvironmentSample {
+    pub direction: Vec3,
+    pub radiance: Vec3,
+    // per unit solid angle
+    pub pdf: f32,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: HdrImage, intensity: f32, rotation: f32) -> Self {
+        let HdrImage { width, height, pixels } = image;
+        let mut conditional_cdf = Vec::with_capacity(height * (width + 1));
+        let mut marginal_cdf = Vec::with_capacity(height + 1);
+        marginal_cdf.push(0.0);
+
+        for y in 0..height {
+            // rows near the poles cover less solid angle
+            let sin_theta = ((y as f32 + 0.5) / height as f32 * PI).sin();
+
+            let mut total = 0.0;
+            conditional_cdf.push(0.0);
+            for x in 0..width {
+                total += luminance(pixels[y * width + x]) * sin_theta;
+                conditional_cdf.push(total);
+            }
+
+            let row_total = total;
+            let row = &mut conditional_cdf[y * (width + 1)..];
+            for value in row[..=width].iter_mut() {
+                *value = if row_total > 0.0 { *value / row_total } else { 0.0 };
+            }
+            marginal_cdf.push(marginal_cdf[y] + row_total);
+        }
+
+        let total = marginal_cdf[height];
+        for value in marginal_cdf.iter_mut() {
+            *value = if total > 0.0 { *value / total } else { 0.0 };
+        }
+
+        EnvironmentMap { width, height, pixels, intensity, rotation, marginal_cdf, conditional_cdf }
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        if self.width == 0 || self.height == 0 {
+            return Vec3::ZERO;
+        }
+        let (x, y) = self.direction_to_pixel(direction);
+        self.pixels[y * self.width + x] * self.intensity
+    }
+
+    // Picks a direction with probability proportional to the pixel luminance
+    pub fn sample(&self, rng: &mut ThreadRng) -> Option<EnvironmentSample> {
+        if self.marginal_cdf.last().is_none_or(|&total| total <= 0.0) {
+            return None;
+        }
+
+        let y = pick(&self.marginal_cdf, rng.random());
+        let row = &self.conditional_cdf[y * (self.width + 1)..(y + 1) * (self.width + 1)];
+        let x = pick(row, rng.random());
+
+        // uniform point inside the chosen pixel
+        let u = (x as f32 + rng.random::<f32>()) / self.width as f32;
+        let v = (y as f32 + rng.random::<f32>()) / self.height as f32;
+        let direction = self.uv_to_direction(u, v);
+
+        // look the direction up again so radiance and pdf agree on the pixel even at its edges
+        let pdf = self.pdf(direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(EnvironmentSample { direction, radiance: self.radiance(direction), pdf })
+    }
+
+    // Solid angle density of sample returning this direction
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        if self.marginal_cdf.last().is_none_or(|&total| total <= 0.0) {
+            return 0.0;
+        }
+
+        let (x, y) = self.direction_to_pixel(direction);
+        let row = &self.conditional_cdf[y * (self.width + 1)..];
+        let pixel_probability = (self.marginal_cdf[y + 1] - self.marginal_cdf[y]) * (row[x + 1] - row[x]);
+
+        // points are uniform in the pixel's uv square, so the jacobian uses the exact sin_theta,
+        // taken from x and z since 1 - y * y rounds to zero next to the poles
+        let direction = direction.normalize();
+        let sin_theta = (direction.x * direction.x + direction.z * direction.z).sqrt();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        // uv maps to solid angle with 2 pi * pi * sin_theta
+        pixel_probability * (self.width * self.height) as f32 / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn direction_to_pixel(&self, direction: Vec3) -> (usize, usize) {
+        let direction = direction.normalize();
+        let phi = direction.x.atan2(-direction.z) - self.rotation.to_radians();
+        let theta = direction.y.clamp(-1.0, 1.0).acos();
+
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        let v = theta / PI;
+
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        (x, y)
+    }
+
+    fn uv_to_direction(&self, u: f32, v: f32) -> Vec3 {
+        let phi = (u - 0.5) * 2.0 * PI + self.rotation.to_radians();
+        let theta = v * PI;
+        Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+    }
+}
+
+// Index of the bucket of a normalized cdf that contains the value
+pub fn pick(cdf: &[f32], value: f32) -> usize {
+    let buckets = cdf.len() - 1;
+    let index = cdf.partition_point(|&c| c <= value).saturating_sub(1);
+
+    // skip empty buckets at the end so a zero probability pixel is never returned
+    let mut index = index.min(buckets - 1);
+    while index > 0 && cdf[index + 1] <= cdf[index] {
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Dim gray map with one bright pixel above the horizon
+    fn map(rotation: f32) -> EnvironmentMap {
+        let (width, height) = (8, 4);
+        let mut pixels = vec![Vec3::splat(0.1); width * height];
+        pixels[width + 5] = Vec3::splat(20.0);
+        EnvironmentMap::new(HdrImage { width, height, pixels }, 2.0, rotation)
+    }
+
+    // Exact integral of the radiance over the sphere, every pixel spans a band of the equirectangular grid
+    fn integral(map: &EnvironmentMap) -> Vec3 {
+        let mut total = Vec3::ZERO;
+        for y in 0..map.height {
+            let top = (y as f32 / map.height as f32 * PI).cos();
+            let bottom = ((y + 1) as f32 / map.height as f32 * PI).cos();
+            for x in 0..map.width {
+                total += map.pixels[y * map.width + x] * (2.0 * PI / map.width as f32) * (top - bottom);
+            }
+        }
+        total * map.intensity
+    }
+
+    #[test]
+    fn importance_sampling_is_consistent() {
+        let rng = &mut rand::rng();
+        for rotation in [0.0, 100.0] {
+            let map = map(rotation);
+            let n = 200_000;
+            let mut estimate = Vec3::ZERO;
+            let mut bright = 0;
+            let mut dropped = 0;
+            for _ in 0..n {
+                // the renderer counts a missing sample as no light, rounding at pixel edges may cause one
+                let Some(sample) = map.sample(rng) else {
+                    dropped += 1;
+                    continue;
+                };
+                assert!((sample.pdf - map.pdf(sample.direction)).abs() <= 1e-4 * sample.pdf);
+                assert_eq!(sample.radiance, map.radiance(sample.direction));
+                estimate += sample.radiance / sample.pdf;
+                if sample.radiance.x > 1.0 {
+                    bright += 1;
+                }
+            }
+            assert!(dropped < 10, "{dropped}");
+            let expected = integral(&map);
+            assert!(((estimate / n as f32) - expected).abs().max_element() < 0.01 * expected.x, "{} {expected}", estimate / n as f32);
+            // the bright pixel outweighs the rest of the map by far
+            assert!(bright as f32 / n as f32 > 0.7, "{bright}");
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let map = map(30.0);
+        let steps = 400;
+        let mut total = 0.0;
+        for i in 0..steps {
+            let cos_theta = 1.0 - 2.0 * (i as f32 + 0.5) / steps as f32;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..steps {
+                let phi = (j as f32 + 0.5) / steps as f32 * 2.0 * PI;
+                total += map.pdf(Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin()));
+            }
+        }
+        let integral = total * 4.0 * PI / (steps * steps) as f32;
+        assert!((integral - 1.0).abs() < 0.01, "{integral}");
+    }
+
+    #[test]
+    fn pick_skips_empty_buckets() {
+        let cdf = [0.0, 0.5, 0.5, 1.0, 1.0];
+        assert_eq!(pick(&cdf, 0.0), 0);
+        assert_eq!(pick(&cdf, 0.5), 2);
+        assert_eq!(pick(&cdf, 0.99), 2);
+        assert_eq!(pick(&cdf, 1.0), 2);
+    }
+}
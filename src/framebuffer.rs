@@ -0,0 +1,24 @@
+use glam::Vec3;
+
+// Linear float image as rendered, rows from top to bottom, nothing is clamped
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub color: Vec<Vec3>,
+    // extra passes, only layered formats like OpenEXR store them
+    pub layers: Vec<Layer>,
+}
+
+// Named pass with one to three channels, unused components of the pixels are ignored
+pub struct Layer {
+    pub name: String,
+    // channel names in the order of the vector components, e.g. ["X", "Y", "Z"]
+    pub channels: &'static [&'static str],
+    pub pixels: Vec<Vec3>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer { width, height, color: vec![Vec3::ZERO; width * height], layers: Vec::new() }
+    }
+}
@@ -1,5 +1,7 @@
+#[allow(clippy::module_inception)]
 pub mod lights {
     use glam::Vec3;
+    use rand::prelude::*;
 
     pub struct PointLight {
         pub position: Vec3,
@@ -7,10 +9,524 @@ pub mod lights {
         pub color: Vec3,
     }
 
+    // Light from infinitely far away, like the sun
+    pub struct DirectionalLight {
+        // direction the light travels in
+        pub direction: Vec3,
+        pub intensity: f32,
+        pub color: Vec3,
+        // apparent size in degrees, 0.0 gives hard shadows, the sun is about 0.53
+        pub angular_diameter: f32,
+    }
+
+    pub struct SpotLight {
+        pub position: Vec3,
+        // direction the cone points in
+        pub direction: Vec3,
+        pub intensity: f32,
+        pub color: Vec3,
+        // half angles in degrees, full intensity inside inner_angle fading to nothing at outer_angle
+        pub inner_angle: f32,
+        pub outer_angle: f32,
+    }
+
+    // Area lights emit a constant radiance of color / 255 * intensity from their surface
+
+    pub struct SphereLight {
+        pub position: Vec3,
+        pub radius: f32,
+        pub intensity: f32,
+        pub color: Vec3,
+    }
+
+    // One sided disk shining along its normal
+    pub struct DiskLight {
+        pub position: Vec3,
+        pub normal: Vec3,
+        pub radius: f32,
+        pub intensity: f32,
+        pub color: Vec3,
+    }
+
+    // One sided rectangle centered on position with full, perpendicular edge vectors u and v, shining along u x v
+    pub struct RectLight {
+        pub position: Vec3,
+        pub u: Vec3,
+        pub v: Vec3,
+        pub intensity: f32,
+        pub color: Vec3,
+    }
+
     pub enum Light {
         Point(PointLight),
-        // Future:
-        // Directional(DirectionalLight),
-        // Spot(SpotLight),
+        Directional(DirectionalLight),
+        Spot(SpotLight),
+        Sphere(SphereLight),
+        Disk(DiskLight),
+        Rect(RectLight),
+    }
+
+    // Direction towards the light from a shading point and the radiance arriving along it
+    pub struct LightSample {
+        pub direction: Vec3,
+        // distance to the light for the shadow ray, infinite for directional lights
+        pub distance: f32,
+        // already divided by the sampling pdf, color is 0..255 like everywhere else
+        pub radiance: Vec3,
+        // solid angle density of direction, infinite for lights only reachable by sampling them
+        pub pdf: f32,
+    }
+
+    // A ray running into the surface of a light
+    pub struct LightHit {
+        pub distance: f32,
+        pub radiance: Vec3,
+        // solid angle density of sample picking this direction from the ray origin
+        pub pdf: f32,
+    }
+
+    impl Light {
+        pub fn sample(&self, point: Vec3, rng: &mut ThreadRng) -> Option<LightSample> {
+            match self {
+                Light::Point(pl) => {
+                    let to_light = pl.position - point;
+                    let distance = to_light.length();
+                    if distance <= 0.0 {
+                        return None;
+                    }
+
+                    Some(LightSample {
+                        direction: to_light / distance,
+                        distance,
+                        radiance: pl.color / 255.0 * (pl.intensity / (distance * distance)),
+                        pdf: f32::INFINITY,
+                    })
+                }
+                Light::Directional(dl) => {
+                    let to_light = (-dl.direction).try_normalize()?;
+
+                    // uniform direction inside the cone the sun disk covers
+                    let half_angle = (dl.angular_diameter * 0.5).to_radians();
+                    let cos_max = half_angle.cos();
+                    let cos_theta = 1.0 - rng.random::<f32>() * (1.0 - cos_max);
+                    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                    let phi = 2.0 * std::f32::consts::PI * rng.random::<f32>();
+                    let (u_axis, v_axis) = to_light.any_orthonormal_pair();
+                    let direction = to_light * cos_theta + (u_axis * phi.cos() + v_axis * phi.sin()) * sin_theta;
+
+                    Some(LightSample {
+                        direction: direction.normalize(),
+                        distance: f32::INFINITY,
+                        radiance: dl.color / 255.0 * dl.intensity,
+                        pdf: if dl.angular_diameter > 0.0 { cone_pdf(cos_max) } else { f32::INFINITY },
+                    })
+                }
+                Light::Spot(sl) => {
+                    let to_light = sl.position - point;
+                    let distance = to_light.length();
+                    if distance <= 0.0 {
+                        return None;
+                    }
+                    let direction = to_light / distance;
+
+                    // smooth falloff between the two cone angles
+                    let cos_angle = sl.direction.normalize_or_zero().dot(-direction);
+                    let cos_inner = sl.inner_angle.to_radians().cos();
+                    let cos_outer = sl.outer_angle.to_radians().cos();
+                    let falloff = if cos_inner > cos_outer {
+                        let t = ((cos_angle - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0);
+                        t * t * (3.0 - 2.0 * t)
+                    } else if cos_angle >= cos_outer {
+                        1.0
+                    } else {
+                        0.0
+                    };
+                    if falloff <= 0.0 {
+                        return None;
+                    }
+
+                    Some(LightSample {
+                        direction,
+                        distance,
+                        radiance: sl.color / 255.0 * (sl.intensity * falloff / (distance * distance)),
+                        pdf: f32::INFINITY,
+                    })
+                }
+                Light::Sphere(sl) => {
+                    let to_center = sl.position - point;
+                    let distance_squared = to_center.length_squared();
+                    let radius = sl.radius.abs();
+                    if distance_squared <= radius * radius {
+                        return None;
+                    }
+                    let distance = distance_squared.sqrt();
+                    let axis = to_center / distance;
+
+                    // uniform direction inside the cone the sphere covers
+                    let sin_max_squared = radius * radius / distance_squared;
+                    let cos_max = (1.0 - sin_max_squared).max(0.0).sqrt();
+                    let cos_theta = 1.0 - rng.random::<f32>() * (1.0 - cos_max);
+                    let sin_theta_squared = (1.0 - cos_theta * cos_theta).max(0.0);
+                    let sin_theta = sin_theta_squared.sqrt();
+                    let phi = 2.0 * std::f32::consts::PI * rng.random::<f32>();
+                    let (u_axis, v_axis) = axis.any_orthonormal_pair();
+                    let direction = axis * cos_theta + (u_axis * phi.cos() + v_axis * phi.sin()) * sin_theta;
+
+                    // near side of the sphere along the sampled direction
+                    let surface_distance = distance * cos_theta
+                        - (radius * radius - distance_squared * sin_theta_squared).max(0.0).sqrt();
+                    let solid_angle = 2.0 * std::f32::consts::PI * (1.0 - cos_max);
+
+                    Some(LightSample {
+                        direction: direction.normalize(),
+                        distance: surface_distance,
+                        radiance: sl.color / 255.0 * (sl.intensity * solid_angle),
+                        pdf: 1.0 / solid_angle,
+                    })
+                }
+                Light::Disk(dl) => {
+                    let normal = dl.normal.try_normalize()?;
+                    let radius = dl.radius.abs();
+
+                    // uniform point on the disk, converted to solid angle below
+                    let r = radius * rng.random::<f32>().sqrt();
+                    let phi = 2.0 * std::f32::consts::PI * rng.random::<f32>();
+                    let (u_axis, v_axis) = normal.any_orthonormal_pair();
+                    let position = dl.position + (u_axis * phi.cos() + v_axis * phi.sin()) * r;
+
+                    let to_light = position - point;
+                    let distance = to_light.length();
+                    if distance <= 0.0 {
+                        return None;
+                    }
+                    let direction = to_light / distance;
+
+                    let cos_light = normal.dot(-direction);
+                    if cos_light <= 0.0 {
+                        return None;
+                    }
+                    let area = std::f32::consts::PI * radius * radius;
+
+                    Some(LightSample {
+                        direction,
+                        distance,
+                        radiance: dl.color / 255.0 * (dl.intensity * cos_light * area / (distance * distance)),
+                        pdf: distance * distance / (cos_light * area),
+                    })
+                }
+                Light::Rect(rl) => {
+                    let corner = rl.position - (rl.u + rl.v) * 0.5;
+                    let normal = rl.u.cross(rl.v).try_normalize()?;
+                    if normal.dot(point - corner) <= 0.0 {
+                        return None;
+                    }
+
+                    // far away points lose the tiny solid angle to f32 precision
+                    let rect = SphericalRect::new(corner, rl.u, rl.v, point)?;
+                    let position = rect.sample(rng.random(), rng.random());
+                    if !position.is_finite() {
+                        return None;
+                    }
+
+                    let to_light = position - point;
+                    let distance = to_light.length();
+                    if distance <= 0.0 {
+                        return None;
+                    }
+
+                    Some(LightSample {
+                        direction: to_light / distance,
+                        distance,
+                        radiance: rl.color / 255.0 * (rl.intensity * rect.solid_angle),
+                        pdf: 1.0 / rect.solid_angle,
+                    })
+                }
+            }
+        }
+
+        // First point where the ray meets the light, None for point and spot lights which have no surface
+        // directional lights sit at infinity and are only met by rays leaving the scene
+        pub fn hit(&self, origin: Vec3, direction: Vec3) -> Option<LightHit> {
+            match self {
+                Light::Point(_) | Light::Spot(_) => None,
+                Light::Directional(dl) => {
+                    let to_light = (-dl.direction).try_normalize()?;
+                    let cos_max = (dl.angular_diameter * 0.5).to_radians().cos();
+                    if dl.angular_diameter <= 0.0 || direction.dot(to_light) < cos_max {
+                        return None;
+                    }
+
+                    // intensity is the irradiance of the whole disk, spread over its solid angle
+                    let pdf = cone_pdf(cos_max);
+                    Some(LightHit { distance: f32::INFINITY, radiance: dl.color / 255.0 * (dl.intensity * pdf), pdf })
+                }
+                Light::Sphere(sl) => {
+                    let radius = sl.radius.abs();
+                    let oc = origin - sl.position;
+                    let c = oc.length_squared() - radius * radius;
+                    if c <= 0.0 {
+                        return None;
+                    }
+
+                    let b = oc.dot(direction);
+                    let discriminant = b * b - c;
+                    if discriminant < 0.0 {
+                        return None;
+                    }
+                    let distance = -b - discriminant.sqrt();
+                    if distance <= 0.0 {
+                        return None;
+                    }
+
+                    let cos_max = (1.0 - radius * radius / oc.length_squared()).max(0.0).sqrt();
+                    Some(LightHit { distance, radiance: sl.color / 255.0 * sl.intensity, pdf: cone_pdf(cos_max) })
+                }
+                Light::Disk(dl) => {
+                    let normal = dl.normal.try_normalize()?;
+                    let cos_light = -direction.dot(normal);
+                    if cos_light <= 0.0 {
+                        return None;
+                    }
+
+                    let distance = (dl.position - origin).dot(normal) / -cos_light;
+                    if distance <= 0.0 || (origin + direction * distance - dl.position).length() > dl.radius.abs() {
+                        return None;
+                    }
+
+                    let area = std::f32::consts::PI * dl.radius * dl.radius;
+                    Some(LightHit {
+                        distance,
+                        radiance: dl.color / 255.0 * dl.intensity,
+                        pdf: distance * distance / (cos_light * area),
+                    })
+                }
+                Light::Rect(rl) => {
+                    let normal = rl.u.cross(rl.v).try_normalize()?;
+                    let cos_light = -direction.dot(normal);
+                    if cos_light <= 0.0 {
+                        return None;
+                    }
+
+                    let distance = (rl.position - origin).dot(normal) / -cos_light;
+                    if distance <= 0.0 {
+                        return None;
+                    }
+                    let offset = origin + direction * distance - rl.position;
+                    if offset.dot(rl.u).abs() > 0.5 * rl.u.length_squared() || offset.dot(rl.v).abs() > 0.5 * rl.v.length_squared() {
+                        return None;
+                    }
+
+                    let rect = SphericalRect::new(rl.position - (rl.u + rl.v) * 0.5, rl.u, rl.v, origin)?;
+                    Some(LightHit { distance, radiance: rl.color / 255.0 * rl.intensity, pdf: 1.0 / rect.solid_angle })
+                }
+            }
+        }
+    }
+
+    // density of uniformly sampling a cone of directions
+    fn cone_pdf(cos_max: f32) -> f32 {
+        1.0 / (2.0 * std::f32::consts::PI * (1.0 - cos_max)).max(1e-12)
+    }
+
+    // Rectangle as seen from a point, for uniform sampling of its solid angle
+    // after Urena et al. 2013, "An Area-Preserving Parametrization for Spherical Rectangles"
+    struct SphericalRect {
+        origin: Vec3,
+        x: Vec3,
+        y: Vec3,
+        z: Vec3,
+        x0: f32,
+        x1: f32,
+        y0: f32,
+        y1: f32,
+        z0: f32,
+        b0: f32,
+        b1: f32,
+        k: f32,
+        solid_angle: f32,
+    }
+
+    impl SphericalRect {
+        fn new(corner: Vec3, u: Vec3, v: Vec3, origin: Vec3) -> Option<Self> {
+            let (u_length, v_length) = (u.length(), v.length());
+            if u_length <= 0.0 || v_length <= 0.0 {
+                return None;
+            }
+
+            // local frame with the rectangle in the plane z = z0 < 0
+            let x = u / u_length;
+            let y = v / v_length;
+            let mut z = x.cross(y);
+            let d = corner - origin;
+            let mut z0 = d.dot(z);
+            if z0 > 0.0 {
+                z = -z;
+                z0 = -z0;
+            }
+            if z0.abs() < 1e-7 {
+                return None;
+            }
+
+            let x0 = d.dot(x);
+            let y0 = d.dot(y);
+            let x1 = x0 + u_length;
+            let y1 = y0 + v_length;
+
+            let v00 = Vec3::new(x0, y0, z0);
+            let v01 = Vec3::new(x0, y1, z0);
+            let v10 = Vec3::new(x1, y0, z0);
+            let v11 = Vec3::new(x1, y1, z0);
+
+            let n0 = v00.cross(v10).normalize();
+            let n1 = v10.cross(v11).normalize();
+            let n2 = v11.cross(v01).normalize();
+            let n3 = v01.cross(v00).normalize();
+
+            let g0 = (-n0.dot(n1)).clamp(-1.0, 1.0).acos();
+            let g1 = (-n1.dot(n2)).clamp(-1.0, 1.0).acos();
+            let g2 = (-n2.dot(n3)).clamp(-1.0, 1.0).acos();
+            let g3 = (-n3.dot(n0)).clamp(-1.0, 1.0).acos();
+
+            let k = 2.0 * std::f32::consts::PI - g2 - g3;
+            let solid_angle = g0 + g1 - k;
+            if solid_angle <= 1e-9 {
+                return None;
+            }
+
+            Some(SphericalRect { origin, x, y, z, x0, x1, y0, y1, z0, b0: n0.z, b1: n2.z, k, solid_angle })
+        }
+
+        fn sample(&self, s: f32, t: f32) -> Vec3 {
+            // pick the x coordinate so the strip left of it covers s * solid_angle
+            let au = s * self.solid_angle + self.k;
+            let fu = (au.cos() * self.b0 - self.b1) / au.sin();
+            let cu = ((1.0 / (fu * fu + self.b0 * self.b0).sqrt()).copysign(fu)).clamp(-1.0, 1.0);
+            let xu = (-(cu * self.z0) / (1.0 - cu * cu).max(1e-12).sqrt()).clamp(self.x0, self.x1);
+
+            // then y uniformly in the projected height of that strip
+            let d = (xu * xu + self.z0 * self.z0).sqrt();
+            let h0 = self.y0 / (d * d + self.y0 * self.y0).sqrt();
+            let h1 = self.y1 / (d * d + self.y1 * self.y1).sqrt();
+            let hv = h0 + t * (h1 - h0);
+            let hv_squared = hv * hv;
+            let yv = if hv_squared < 1.0 - 1e-6 { hv * d / (1.0 - hv_squared).sqrt() } else { self.y1 };
+
+            self.origin + self.x * xu + self.y * yv + self.z * self.z0
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn area_lights() -> Vec<Light> {
+            let color = Vec3::new(255.0, 128.0, 64.0);
+            vec![
+                Light::Sphere(SphereLight { position: Vec3::new(0.0, 3.0, 0.5), radius: 1.0, intensity: 2.0, color }),
+                Light::Disk(DiskLight { position: Vec3::new(0.5, 2.0, 0.0), normal: Vec3::new(0.2, -1.0, 0.1), radius: 1.2, intensity: 2.0, color }),
+                Light::Rect(RectLight {
+                    position: Vec3::new(-0.3, 2.0, 0.2),
+                    u: Vec3::new(2.0, 0.0, 0.0),
+                    v: Vec3::new(0.0, 0.0, 1.5),
+                    intensity: 2.0,
+                    color,
+                }),
+            ]
+        }
+
+        fn assert_close(a: f32, b: f32, tolerance: f32) {
+            assert!((a - b).abs() <= tolerance * b.abs().max(1e-6), "{a} != {b}");
+        }
+
+        #[test]
+        fn directional_light() {
+            let rng = &mut rand::rng();
+            let mut sun = DirectionalLight { direction: Vec3::new(0.0, -2.0, 0.0), intensity: 3.0, color: Vec3::splat(255.0), angular_diameter: 0.0 };
+
+            // a hard sun is a delta towards -direction, with no falloff over distance
+            let near = Light::Directional(DirectionalLight { ..sun }).sample(Vec3::ZERO, rng).unwrap();
+            let far = Light::Directional(DirectionalLight { ..sun }).sample(Vec3::splat(1000.0), rng).unwrap();
+            assert_eq!(near.direction, Vec3::Y);
+            assert!(near.pdf.is_infinite() && near.distance.is_infinite());
+            assert_eq!(near.radiance, Vec3::splat(3.0));
+            assert_eq!(far.radiance, near.radiance);
+
+            // a soft sun samples its disk, the pdf is one over the cone's solid angle
+            sun.angular_diameter = 10.0;
+            let soft = Light::Directional(sun);
+            let cos_max = 5.0f32.to_radians().cos();
+            for _ in 0..1000 {
+                let sample = soft.sample(Vec3::ZERO, rng).unwrap();
+                assert!(sample.direction.y >= cos_max - 1e-6);
+                assert_close(sample.pdf, 1.0 / (2.0 * std::f32::consts::PI * (1.0 - cos_max)), 1e-3);
+            }
+        }
+
+        #[test]
+        fn spot_light_cone() {
+            let rng = &mut rand::rng();
+            let spot = Light::Spot(SpotLight {
+                position: Vec3::new(0.0, 2.0, 0.0),
+                direction: Vec3::NEG_Y,
+                intensity: 8.0,
+                color: Vec3::splat(255.0),
+                inner_angle: 20.0,
+                outer_angle: 40.0,
+            });
+            let mut at_angle = |degrees: f32| {
+                let point = Vec3::new(2.0 * degrees.to_radians().tan(), 0.0, 0.0);
+                spot.sample(point, rng).map(|sample| sample.radiance.x * (point - Vec3::new(0.0, 2.0, 0.0)).length_squared())
+            };
+
+            // inverse square falloff on the axis, full strength inside the inner cone
+            assert_close(at_angle(0.0).unwrap(), 8.0, 1e-5);
+            assert_close(at_angle(15.0).unwrap(), 8.0, 1e-5);
+            // the edge fades smoothly and nothing is lit past the outer cone
+            let fading = at_angle(30.0).unwrap();
+            assert!(fading > 0.0 && fading < 8.0, "{fading}");
+            assert!(at_angle(35.0).unwrap() < fading);
+            assert!(at_angle(45.0).is_none());
+        }
+
+        #[test]
+        fn sample_agrees_with_hit() {
+            let rng = &mut rand::rng();
+            let point = Vec3::new(0.1, 0.0, -0.2);
+
+            for light in area_lights() {
+                for _ in 0..1000 {
+                    let sample = light.sample(point, rng).expect("the point sees the light");
+                    let hit = light.hit(point, sample.direction).expect("sampled directions hit the light");
+
+                    assert_close(hit.distance, sample.distance, 1e-3);
+                    assert_close(hit.pdf, sample.pdf, 1e-3);
+                    // the sample radiance is already divided by the pdf
+                    assert_close(sample.radiance.x, hit.radiance.x / hit.pdf, 1e-3);
+                }
+            }
+        }
+
+        #[test]
+        fn pdf_integrates_to_one() {
+            let rng = &mut rand::rng();
+            let point = Vec3::new(0.1, 0.0, -0.2);
+            let count = 400_000;
+
+            for light in area_lights() {
+                // uniform directions over the sphere, the density of each is 1 / 4 pi
+                let mut total = 0.0f64;
+                for _ in 0..count {
+                    let z: f32 = rng.random_range(-1.0..1.0);
+                    let phi = 2.0 * std::f32::consts::PI * rng.random::<f32>();
+                    let r = (1.0 - z * z).max(0.0).sqrt();
+                    let direction = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+                    if let Some(hit) = light.hit(point, direction) {
+                        total += hit.pdf as f64;
+                    }
+                }
+                let integral = total * 4.0 * std::f64::consts::PI / count as f64;
+                assert!((integral - 1.0).abs() < 0.05, "pdf integrates to {integral}");
+            }
+        }
     }
 }
@@ -0,0 +1,117 @@
+use std::path::Path;
+
+use glam::{Vec2, Vec3};
+
+use crate::lights::lights::Light;
+use crate::primitives::primitives::*;
+use crate::renderer::Camera;
+
+pub mod gltf;
+pub mod hdr;
+pub mod mask;
+pub mod obj;
+pub mod ply;
+pub mod stl;
+pub mod texture;
+
+// Geometry read from a file, kept apart from the materials so that the
+// TriangleMesh can borrow its Material from the same loaded model
+pub struct MeshData {
+    pub name: String,
+    pub vertices: Vec<Vec3>,
+    pub indices: Vec<[u32; 3]>,
+    pub normals: Option<Vec<Vec3>>,
+    pub uvs: Option<Vec<Vec2>>,
+    // per vertex colors in the same 0..255 range as Material::color
+    pub colors: Option<Vec<Vec3>>,
+    // index into Model::materials
+    pub material: Option<usize>,
+}
+
+pub struct Model {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<Material>,
+}
+
+// Everything a scene file can bring along, only glTF carries cameras and lights
+pub struct SceneFile {
+    pub model: Model,
+    pub cameras: Vec<Camera>,
+    pub lights: Vec<Light>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Parse(String),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "io error: {err}"),
+            LoadError::Parse(msg) => write!(f, "parse error: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(err: std::io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+impl MeshData {
+    pub fn into_mesh(self, material: &Material) -> TriangleMesh<'_> {
+        let has_normals = self.normals.is_some();
+        let mut mesh = TriangleMesh::new(self.vertices, self.indices, self.normals, self.uvs, material);
+        mesh.colors = self.colors;
+        // stl never has normals and obj, ply and gltf files may leave them out
+        if !has_normals {
+            mesh.compute_normals();
+        }
+        mesh
+    }
+}
+
+// Turns every mesh into a TriangleMesh using its own material from the model,
+// meshes without one fall back to the given material
+pub fn build_meshes<'a>(meshes: Vec<MeshData>, materials: &'a [Material], fallback: &'a Material) -> Vec<TriangleMesh<'a>> {
+    meshes
+        .into_iter()
+        .map(|mesh| {
+            let material = match mesh.material {
+                Some(index) => materials.get(index).unwrap_or_else(|| {
+                    eprintln!("warning: mesh {} refers to missing material {index}, using the default", mesh.name);
+                    fallback
+                }),
+                None => fallback,
+            };
+            mesh.into_mesh(material)
+        })
+        .collect()
+}
+
+// Loads any of the supported formats, picked by the file extension
+pub fn load_scene(path: impl AsRef<Path>) -> Result<SceneFile, LoadError> {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+
+    // single mesh formats have no materials, the fallback material is used for them
+    let single_mesh = |mesh: MeshData| Model { meshes: vec![mesh], materials: Vec::new() };
+
+    let model = match extension.as_str() {
+        "gltf" | "glb" => {
+            let scene = gltf::load_gltf(path)?;
+            return Ok(SceneFile { model: scene.model, cameras: scene.cameras, lights: scene.lights });
+        }
+        "obj" => obj::load_obj(path)?,
+        "ply" => single_mesh(ply::load_ply(path)?),
+        "stl" => single_mesh(stl::load_stl(path)?),
+        _ => return Err(LoadError::Parse(format!("unsupported scene format {}", path.display()))),
+    };
+
+    Ok(SceneFile { model, cameras: Vec::new(), lights: Vec::new() })
+}
@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use glam::{Mat3, Mat4, Vec2, Vec3};
+
+use crate::lights::lights::{DirectionalLight, DiskLight, Light, PointLight, RectLight, SphereLight, SpotLight};
+use crate::primitives::primitives::*;
+use crate::renderer::{ApertureShape, Camera, Projection};
+use crate::texture::Texture;
+use super::texture::{decode_texture, load_texture};
+use super::{LoadError, MeshData, Model};
+
+pub struct GltfScene {
+    pub model: Model,
+    pub cameras: Vec<Camera>,
+    pub lights: Vec<Light>,
+}
+
+// Loads a .gltf or .glb file, flattening the node hierarchy of the default scene
+// into world space meshes, cameras and lights
+pub fn load_gltf(path: impl AsRef<Path>) -> Result<GltfScene, LoadError> {
+    let path = path.as_ref();
+
+    let gltf = gltf::Gltf::open(path).map_err(|err| convert_error(path, err))?;
+    let buffers = gltf::import_buffers(&gltf.document, path.parent(), gltf.blob.clone())
+        .map_err(|err| convert_error(path, err))?;
+
+    let document = &gltf.document;
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    // only base color textures are used, materials may share their images
+    let mut images: HashMap<usize, Option<Arc<Texture>>> = HashMap::new();
+    let materials = document
+        .materials()
+        .map(|material| {
+            let texture = material.pbr_metallic_roughness().base_color_texture().and_then(|info| {
+                let image = info.texture().source();
+                let texture = images.entry(image.index()).or_insert_with(|| {
+                    load_image(&image, directory, &buffers)
+                        .inspect_err(|err| eprintln!("warning: failed to load texture {} of {}: {err}", image.index(), path.display()))
+                        .ok()
+                        .map(Arc::new)
+                });
+                texture.clone()
+            });
+            convert_material(&material, texture)
+        })
+        .collect();
+
+    let mut scene = GltfScene {
+        model: Model { meshes: Vec::new(), materials },
+        cameras: Vec::new(),
+        lights: Vec::new(),
+    };
+
+    let Some(root) = document.default_scene().or_else(|| document.scenes().next()) else {
+        return Ok(scene);
+    };
+
+    for node in root.nodes() {
+        visit_node(&node, Mat4::IDENTITY, &buffers, &mut scene).map_err(|err| match err {
+            LoadError::Parse(msg) => LoadError::Parse(format!("{}: {msg}", path.display())),
+            err => err,
+        })?;
+    }
+
+    Ok(scene)
+}
+
+fn visit_node(node: &gltf::Node, parent_transform: Mat4, buffers: &[gltf::buffer::Data], scene: &mut GltfScene) -> Result<(), LoadError> {
+    let transform = parent_transform * Mat4::from_cols_array_2d(&node.transform().matrix());
+
+    if let Some(mesh) = node.mesh() {
+        let name = mesh.name().or(node.name()).unwrap_or_default();
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                eprintln!("warning: skipping non triangle primitive in mesh {name}");
+                continue;
+            }
+            if let Some(data) = convert_primitive(name, &primitive, transform, buffers)? {
+                scene.model.meshes.push(data);
+            }
+        }
+    }
+
+    if let Some(camera) = node.camera() {
+        scene.cameras.push(convert_camera(&camera, transform));
+    }
+
+    if let Some(light) = node.light() {
+        let position = transform.transform_point3(Vec3::ZERO);
+        // lights shine down their local -z axis
+        let direction = transform.transform_vector3(Vec3::NEG_Z).normalize_or(Vec3::NEG_Z);
+        let color = Vec3::from(light.color()) * 255.0;
+
+        let punctual = match light.kind() {
+            gltf::khr_lights_punctual::Kind::Point => Light::Point(PointLight {
+                position,
+                intensity: light.intensity(),
+                color,
+            }),
+            gltf::khr_lights_punctual::Kind::Directional => Light::Directional(DirectionalLight {
+                direction,
+                intensity: light.intensity(),
+                color,
+                angular_diameter: 0.0,
+            }),
+            gltf::khr_lights_punctual::Kind::Spot { inner_cone_angle, outer_cone_angle } => Light::Spot(SpotLight {
+                position,
+                direction,
+                intensity: light.intensity(),
+                color,
+                inner_angle: inner_cone_angle.to_degrees(),
+                outer_angle: outer_cone_angle.to_degrees(),
+            }),
+        };
+        scene.lights.push(convert_area_light(&light, transform, color).unwrap_or(punctual));
+    }
+
+    for child in node.children() {
+        visit_node(&child, transform, buffers, scene)?;
+    }
+    Ok(())
+}
+
+// KHR_lights_punctual only knows infinitely small lights, the extras of a light can give it a shape:
+// {"shape": "sphere", "radius": r}, {"shape": "disk", "radius": r} or {"shape": "rect", "width": w, "height": h}
+// in world units. Disks and rectangles face the local -z axis like spot lights do. The intensity keeps
+// its meaning of candela towards the viewer and is spread over the area the shape shows from there.
+fn convert_area_light(light: &gltf::khr_lights_punctual::Light, transform: Mat4, color: Vec3) -> Option<Light> {
+    let extras: serde_json::Value = serde_json::from_str(light.extras().as_ref()?.get()).ok()?;
+    let size = |key: &str| {
+        let value = extras.get(key).and_then(|v| v.as_f64()).map(|v| v as f32).filter(|v| v.is_finite() && *v > 0.0);
+        if value.is_none() {
+            eprintln!("warning: area light {} needs a positive {key}", light.name().unwrap_or_default());
+        }
+        value
+    };
+
+    let position = transform.transform_point3(Vec3::ZERO);
+    let intensity = light.intensity();
+    let pi = std::f32::consts::PI;
+
+    match extras.get("shape")?.as_str()? {
+        "sphere" => {
+            let radius = size("radius")?;
+            Some(Light::Sphere(SphereLight { position, radius, intensity: intensity / (pi * radius * radius), color }))
+        }
+        "disk" => {
+            let radius = size("radius")?;
+            let normal = transform.transform_vector3(Vec3::NEG_Z).normalize_or(Vec3::NEG_Z);
+            Some(Light::Disk(DiskLight { position, normal, radius, intensity: intensity / (pi * radius * radius), color }))
+        }
+        "rect" => {
+            let (width, height) = (size("width")?, size("height")?);
+            // u x v has to point down -z
+            let u = transform.transform_vector3(Vec3::X).normalize_or(Vec3::X) * width;
+            let v = transform.transform_vector3(Vec3::NEG_Y).normalize_or(Vec3::NEG_Y) * height;
+            Some(Light::Rect(RectLight { position, u, v, intensity: intensity / (width * height), color }))
+        }
+        other => {
+            eprintln!("warning: unknown light shape {other}, using a punctual light");
+            None
+        }
+    }
+}
+
+fn convert_primitive(
+    name: &str,
+    primitive: &gltf::Primitive,
+    transform: Mat4,
+    buffers: &[gltf::buffer::Data]
+) -> Result<Option<MeshData>, LoadError> {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+    let Some(positions) = reader.read_positions() else {
+        return Ok(None);
+    };
+    let vertices: Vec<Vec3> = positions.map(|p| transform.transform_point3(Vec3::from(p))).collect();
+    // attributes with a different count than the positions cannot be indexed the same way
+    let complete = |len: usize| {
+        if len != vertices.len() {
+            eprintln!("warning: ignoring a vertex attribute of mesh {name} with {len} instead of {} entries", vertices.len());
+        }
+        len == vertices.len()
+    };
+
+    // normals transform with the inverse transpose
+    let normal_matrix = Mat3::from_mat4(transform).inverse().transpose();
+    let normals = reader
+        .read_normals()
+        .map(|normals| normals.map(|n| (normal_matrix * Vec3::from(n)).normalize_or_zero()).collect::<Vec<_>>())
+        .filter(|normals| complete(normals.len()));
+
+    // glTF puts v = 0 at the top of the image, textures here expect it at the bottom
+    let uvs = reader
+        .read_tex_coords(0)
+        .map(|uvs| uvs.into_f32().map(|[u, v]| Vec2::new(u, 1.0 - v)).collect::<Vec<_>>())
+        .filter(|uvs| complete(uvs.len()));
+
+    let flat_indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..vertices.len() as u32).collect(),
+    };
+    if flat_indices.iter().any(|&i| i as usize >= vertices.len()) {
+        return Err(LoadError::Parse(format!("index out of range in mesh {name}")));
+    }
+
+    // a mirroring transform flips the winding
+    let mut indices: Vec<[u32; 3]> = flat_indices
+        .chunks_exact(3)
+        .map(|i| [i[0], i[1], i[2]])
+        .collect();
+    if transform.determinant() < 0.0 {
+        for tri in indices.iter_mut() {
+            tri.swap(1, 2);
+        }
+    }
+
+    Ok(Some(MeshData {
+        name: name.to_string(),
+        vertices,
+        indices,
+        normals,
+        uvs,
+        colors: None,
+        material: primitive.material().index(),
+    }))
+}
+
+// Images either sit in a buffer of the file or next to it, only png is supported
+fn load_image(image: &gltf::Image, directory: &Path, buffers: &[gltf::buffer::Data]) -> Result<Texture, LoadError> {
+    match image.source() {
+        gltf::image::Source::View { view, mime_type } => {
+            if mime_type != "image/png" {
+                return Err(LoadError::Parse(format!("unsupported texture type {mime_type}")));
+            }
+            let bytes = buffers[view.buffer().index()]
+                .get(view.offset()..view.offset() + view.length())
+                .ok_or_else(|| LoadError::Parse("texture buffer view out of range".to_string()))?;
+            decode_texture(bytes)
+        }
+        gltf::image::Source::Uri { uri, .. } => {
+            if uri.starts_with("data:") {
+                return Err(LoadError::Parse("embedded data uri textures are not supported".to_string()));
+            }
+            load_texture(directory.join(uri))
+        }
+    }
+}
+
+fn convert_material(material: &gltf::Material, texture: Option<Arc<Texture>>) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, _] = pbr.base_color_factor();
+    let emissive_strength = material.emissive_strength().unwrap_or(1.0);
+
+    // mostly transmissive materials become glass, KHR_materials_volume gives the absorption
+    let transmission = material.transmission().map_or(0.0, |t| t.transmission_factor());
+    let kind = if transmission > 0.5 {
+        let absorption = material.volume().map_or(Vec3::ZERO, |volume| {
+            let distance = volume.attenuation_distance();
+            let color = Vec3::from(volume.attenuation_color()).max(Vec3::splat(1e-6));
+            if distance.is_finite() && distance > 0.0 {
+                -Vec3::new(color.x.ln(), color.y.ln(), color.z.ln()) / distance
+            } else {
+                Vec3::ZERO
+            }
+        });
+        MaterialKind::Dielectric { ior: material.ior().unwrap_or(1.5), absorption }
+    } else {
+        MaterialKind::Standard
+    };
+
+    Material {
+        color: Vec3::new(r, g, b) * 255.0,
+        // multiplies the base color factor, as in the glTF spec
+        color_texture: texture,
+        roughness: pbr.roughness_factor(),
+        emission: Vec3::from(material.emissive_factor()) * emissive_strength,
+        kind,
+        // the principled material reads metallic the same way glTF does
+        metallic: pbr.metallic_factor(),
+        transmission,
+        ior: material.ior().unwrap_or(1.5),
+        ..Default::default()
+    }
+}
+
+fn convert_camera(camera: &gltf::Camera, transform: Mat4) -> Camera {
+    // glTF cameras look down their local -z axis with +y up
+    let position = transform.transform_point3(Vec3::ZERO);
+    let forward = transform.transform_vector3(Vec3::NEG_Z).normalize();
+    let up = transform.transform_vector3(Vec3::Y).normalize();
+
+    let (fov, projection) = match camera.projection() {
+        gltf::camera::Projection::Perspective(perspective) => {
+            (perspective.yfov().to_degrees(), Projection::Perspective)
+        }
+        gltf::camera::Projection::Orthographic(orthographic) => {
+            (90.0, Projection::Orthographic { height: orthographic.ymag() * 2.0 })
+        }
+    };
+
+    Camera {
+        position,
+        look_at: position + forward,
+        up,
+        fov,
+        background_color: Vec3::ZERO,
+        aperture_radius: 0.0,
+        focus_distance: 1.0,
+        aperture_shape: ApertureShape::Circle,
+        projection,
+    }
+}
+
+fn convert_error(path: &Path, err: gltf::Error) -> LoadError {
+    match err {
+        gltf::Error::Io(err) => LoadError::Io(err),
+        err => LoadError::Parse(format!("{}: {err}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One triangle mesh with its buffer in a .bin file next to the .gltf, in a fresh directory
+    fn write_gltf(name: &str, positions: &[[f32; 3]], indices: &[u16]) -> std::path::PathBuf {
+        let directory = std::env::temp_dir().join(format!("ray_tracer_gltf_{}_{name}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let mut bin: Vec<u8> = positions.iter().flatten().flat_map(|v| v.to_le_bytes()).collect();
+        let positions_length = bin.len();
+        bin.extend(indices.iter().flat_map(|i| i.to_le_bytes()));
+        bin.resize(bin.len().next_multiple_of(4), 0);
+        std::fs::write(directory.join("mesh.bin"), &bin).unwrap();
+
+        let json = format!(
+            r#"{{
+                "asset": {{"version": "2.0"}},
+                "scene": 0,
+                "scenes": [{{"nodes": [0]}}],
+                "nodes": [{{"mesh": 0, "translation": [0, 0, -1]}}],
+                "meshes": [{{"name": "{name}", "primitives": [{{"attributes": {{"POSITION": 0}}, "indices": 1, "material": 0}}]}}],
+                "materials": [{{"pbrMetallicRoughness": {{"baseColorFactor": [1, 0.5, 0, 1], "roughnessFactor": 0.25}}}}],
+                "buffers": [{{"uri": "mesh.bin", "byteLength": {}}}],
+                "bufferViews": [
+                    {{"buffer": 0, "byteOffset": 0, "byteLength": {positions_length}}},
+                    {{"buffer": 0, "byteOffset": {positions_length}, "byteLength": {}}}
+                ],
+                "accessors": [
+                    {{"bufferView": 0, "componentType": 5126, "count": {}, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0]}},
+                    {{"bufferView": 1, "componentType": 5123, "count": {}, "type": "SCALAR"}}
+                ]
+            }}"#,
+            bin.len(),
+            indices.len() * 2,
+            positions.len(),
+            indices.len(),
+        );
+        let path = directory.join("mesh.gltf");
+        std::fs::write(&path, json).unwrap();
+        path
+    }
+
+    const TRIANGLE: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+    #[test]
+    fn round_trip() {
+        let path = write_gltf("triangle", &TRIANGLE, &[0, 1, 2]);
+        let result = load_gltf(&path);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        let scene = result.unwrap();
+
+        // the node translation is baked into the vertices
+        let [mesh] = scene.model.meshes.as_slice() else { panic!("expected one mesh") };
+        assert_eq!(mesh.name, "triangle");
+        assert_eq!(mesh.vertices, [Vec3::new(0.0, 0.0, -1.0), Vec3::new(1.0, 0.0, -1.0), Vec3::new(0.0, 1.0, -1.0)]);
+        assert_eq!(mesh.indices, [[0, 1, 2]]);
+        assert!(mesh.normals.is_none() && mesh.uvs.is_none());
+
+        let material = &scene.model.materials[mesh.material.unwrap()];
+        assert_eq!(material.color, Vec3::new(255.0, 127.5, 0.0));
+        assert_eq!(material.roughness, 0.25);
+        assert_eq!(material.emission, Vec3::ZERO);
+        assert!(scene.cameras.is_empty() && scene.lights.is_empty());
+    }
+
+    #[test]
+    fn index_out_of_range() {
+        let path = write_gltf("broken", &TRIANGLE, &[0, 1, 3]);
+        let result = load_gltf(&path);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        match result {
+            Err(LoadError::Parse(msg)) => assert!(msg.contains("index out of range"), "{msg}"),
+            _ => panic!("an index past the vertices has to fail"),
+        }
+    }
+}
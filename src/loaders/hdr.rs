@@ -0,0 +1,204 @@
+use std::path::Path;
+
+use glam::Vec3;
+
+use super::LoadError;
+
+// Linear float image, rows from top to bottom
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+// Reads Radiance .hdr (RGBE) files, flat or with the adaptive run length encoding
+pub fn load_hdr(path: impl AsRef<Path>) -> Result<HdrImage, LoadError> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+    parse_hdr(&bytes).map_err(|err| match err {
+        LoadError::Parse(msg) => LoadError::Parse(format!("{}: {msg}", path.display())),
+        err => err,
+    })
+}
+
+pub(crate) fn parse_hdr(bytes: &[u8]) -> Result<HdrImage, LoadError> {
+    let mut offset = 0;
+    let mut next_line = || -> Result<String, LoadError> {
+        let start = offset;
+        let end = bytes[start..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map(|p| start + p)
+            .ok_or_else(|| LoadError::Parse("unexpected end of hdr header".to_string()))?;
+        offset = end + 1;
+        Ok(String::from_utf8_lossy(&bytes[start..end]).trim().to_string())
+    };
+
+    let magic = next_line()?;
+    if !magic.starts_with("#?") {
+        return Err(LoadError::Parse("not a radiance hdr file".to_string()));
+    }
+
+    // header variables end at the first blank line, only the pixel format matters here
+    loop {
+        let line = next_line()?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(LoadError::Parse(format!("unsupported hdr format {format}")));
+            }
+        }
+    }
+
+    // the usual orientation is "-Y height +X width", +Y stores the rows bottom up
+    let resolution = next_line()?;
+    let words: Vec<&str> = resolution.split_whitespace().collect();
+    let (flip, height, width) = match words.as_slice() {
+        [y @ ("-Y" | "+Y"), height, "+X", width] => {
+            let parse = |value: &str| {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| LoadError::Parse(format!("invalid hdr resolution {resolution}")))
+            };
+            (*y == "+Y", parse(height)?, parse(width)?)
+        }
+        _ => return Err(LoadError::Parse(format!("unsupported hdr resolution {resolution}"))),
+    };
+
+    // the smallest a scanline can be stored in, flat or as runs of 127 per channel,
+    // so a broken resolution is caught before anything is allocated for it
+    let mut data = &bytes[offset..];
+    let min_scanline = if (8..0x8000).contains(&width) { Some(4 + 8 * width.div_ceil(127)) } else { width.checked_mul(4) };
+    let fits = min_scanline
+        .and_then(|size| size.max(4).checked_mul(height))
+        .is_some_and(|size| size <= data.len());
+    if !fits {
+        return Err(LoadError::Parse(format!("hdr resolution {resolution} does not fit the file")));
+    }
+
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut scanline = vec![[0u8; 4]; width];
+
+    for _ in 0..height {
+        data = read_scanline(data, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_rgb(rgbe)));
+    }
+
+    if flip {
+        let rows: Vec<&[Vec3]> = pixels.chunks_exact(width).rev().collect();
+        pixels = rows.concat();
+    }
+
+    Ok(HdrImage { width, height, pixels })
+}
+
+// Decodes one scanline into rgbe quadruples and returns the remaining data
+fn read_scanline<'a>(data: &'a [u8], scanline: &mut [[u8; 4]]) -> Result<&'a [u8], LoadError> {
+    let width = scanline.len();
+    let truncated = || LoadError::Parse("unexpected end of hdr data".to_string());
+
+    // new style rle starts with 2, 2 and the scanline width, then stores each channel separately
+    let is_rle = (8..0x8000).contains(&width)
+        && data.len() >= 4
+        && data[0] == 2
+        && data[1] == 2
+        && ((data[2] as usize) << 8 | data[3] as usize) == width;
+
+    if !is_rle {
+        let size = width * 4;
+        if data.len() < size {
+            return Err(truncated());
+        }
+        for (pixel, raw) in scanline.iter_mut().zip(data.chunks_exact(4)) {
+            pixel.copy_from_slice(raw);
+        }
+        return Ok(&data[size..]);
+    }
+
+    let mut data = &data[4..];
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let (&count, rest) = data.split_first().ok_or_else(truncated)?;
+            data = rest;
+
+            if count > 128 {
+                // a run of one repeated value
+                let run = (count - 128) as usize;
+                let (&value, rest) = data.split_first().ok_or_else(truncated)?;
+                data = rest;
+                if x + run > width {
+                    return Err(LoadError::Parse("hdr run overflows the scanline".to_string()));
+                }
+                for pixel in scanline[x..x + run].iter_mut() {
+                    pixel[channel] = value;
+                }
+                x += run;
+            } else {
+                // count literal values
+                let run = count as usize;
+                if run == 0 || x + run > width {
+                    return Err(LoadError::Parse("invalid hdr run length".to_string()));
+                }
+                if data.len() < run {
+                    return Err(truncated());
+                }
+                for (pixel, &value) in scanline[x..x + run].iter_mut().zip(data) {
+                    pixel[channel] = value;
+                }
+                data = &data[run..];
+                x += run;
+            }
+        }
+    }
+
+    Ok(data)
+}
+
+// shared exponent encoding, a zero exponent is black
+fn rgbe_to_rgb([r, g, b, e]: [u8; 4]) -> Vec3 {
+    if e == 0 {
+        return Vec3::ZERO;
+    }
+    let scale = 2.0f32.powi(e as i32 - 136);
+    Vec3::new(r as f32 + 0.5, g as f32 + 0.5, b as f32 + 0.5) * scale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(resolution: &str) -> Vec<u8> {
+        format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{resolution}\n").into_bytes()
+    }
+
+    #[test]
+    fn flat_pixels() {
+        let mut bytes = header("-Y 1 +X 2");
+        bytes.extend_from_slice(&[128, 64, 0, 129, 0, 0, 0, 0]);
+        let image = parse_hdr(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels[0], Vec3::new(128.5, 64.5, 0.5) / 128.0);
+        assert_eq!(image.pixels[1], Vec3::ZERO);
+    }
+
+    #[test]
+    fn resolution_larger_than_the_file() {
+        for resolution in ["-Y 4000000000 +X 4000000000", "-Y 18446744073709551615 +X 16", "-Y 2 +X 100000", "-Y 3 +X 2"] {
+            let mut bytes = header(resolution);
+            bytes.extend_from_slice(&[1, 1, 1, 128, 1, 1, 1, 128, 1, 1, 1, 128, 1, 1, 1, 128]);
+            assert!(matches!(parse_hdr(&bytes), Err(LoadError::Parse(_))), "{resolution}");
+        }
+    }
+
+    #[test]
+    fn truncated_run_length_data() {
+        let mut bytes = header("-Y 2 +X 8");
+        // rle scanline start for width 8, then a single run that leaves the channels unfinished
+        bytes.extend_from_slice(&[2, 2, 0, 8, 136, 10]);
+        bytes.extend_from_slice(&[0; 12]);
+        assert!(matches!(parse_hdr(&bytes), Err(LoadError::Parse(_))));
+    }
+}
@@ -0,0 +1,25 @@
+use std::path::Path;
+
+use crate::renderer::ApertureMask;
+use super::texture::decode_png;
+use super::LoadError;
+
+// Reads a .png aperture mask, the brightness of each pixel is the transmission of the lens there
+pub fn load_aperture_mask(path: impl AsRef<Path>) -> Result<ApertureMask, LoadError> {
+    let path = path.as_ref();
+    let parse_error = |msg: String| LoadError::Parse(format!("{}: {msg}", path.display()));
+
+    let bytes = std::fs::read(path)?;
+    let (width, height, pixels) = decode_png(&bytes).map_err(|err| match err {
+        LoadError::Parse(msg) => parse_error(msg),
+        err => err,
+    })?;
+
+    // alpha, when present, also blocks light
+    let data: Vec<f32> = pixels
+        .iter()
+        .map(|rgba| (0.2126 * rgba.x + 0.7152 * rgba.y + 0.0722 * rgba.z) * rgba.w)
+        .collect();
+
+    ApertureMask::new(width, height, &data).ok_or_else(|| parse_error("the aperture mask lets no light through".to_string()))
+}
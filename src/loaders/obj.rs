@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use glam::{Vec2, Vec3};
+
+use crate::primitives::primitives::*;
+use crate::texture::Texture;
+use super::texture::load_texture;
+use super::{LoadError, MeshData, Model};
+
+// Loads a Wavefront .obj file together with the .mtl libraries it references
+// every group / object and every material change inside it becomes its own mesh
+pub fn load_obj(path: impl AsRef<Path>) -> Result<Model, LoadError> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Err(LoadError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} not found", path.display()),
+        )));
+    }
+
+    let (models, mtl_result) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)
+        .map_err(|err| LoadError::Parse(format!("{}: {err}", path.display())))?;
+
+    // a broken or missing material library should not stop the geometry from loading
+    let obj_materials = match mtl_result {
+        Ok(materials) => materials,
+        Err(err) => {
+            eprintln!("warning: failed to load materials for {}: {err}", path.display());
+            Vec::new()
+        }
+    };
+
+    let meshes = models
+        .into_iter()
+        .filter(|model| !model.mesh.indices.is_empty())
+        .map(|model| convert_mesh(model.name, model.mesh))
+        .collect();
+
+    // texture paths are relative to the obj file, materials often share the same files
+    // only png is decoded, other formats are reported and the material keeps its plain color
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut textures: HashMap<&str, Option<Arc<Texture>>> = HashMap::new();
+    let materials = obj_materials
+        .iter()
+        .map(|mtl| {
+            let texture = mtl.diffuse_texture.as_deref().and_then(|file| {
+                let texture = textures.entry(file).or_insert_with(|| {
+                    let file_path = directory.join(file);
+                    load_texture(&file_path)
+                        .inspect_err(|err| eprintln!("warning: failed to load texture {}: {err}", file_path.display()))
+                        .ok()
+                        .map(Arc::new)
+                });
+                texture.clone()
+            });
+            convert_material(mtl, texture)
+        })
+        .collect();
+
+    Ok(Model { meshes, materials })
+}
+
+fn convert_mesh(name: String, mesh: tobj::Mesh) -> MeshData {
+    let vertices = mesh.positions
+        .chunks_exact(3)
+        .map(|p| Vec3::new(p[0], p[1], p[2]))
+        .collect();
+
+    let indices = mesh.indices
+        .chunks_exact(3)
+        .map(|i| [i[0], i[1], i[2]])
+        .collect();
+
+    let normals = (!mesh.normals.is_empty()).then(|| {
+        mesh.normals
+            .chunks_exact(3)
+            .map(|n| Vec3::new(n[0], n[1], n[2]).normalize_or_zero())
+            .collect()
+    });
+
+    let uvs = (!mesh.texcoords.is_empty()).then(|| {
+        mesh.texcoords
+            .chunks_exact(2)
+            .map(|t| Vec2::new(t[0], t[1]))
+            .collect()
+    });
+
+    MeshData {
+        name,
+        vertices,
+        indices,
+        normals,
+        uvs,
+        colors: None,
+        material: mesh.material_id,
+    }
+}
+
+fn convert_material(mtl: &tobj::Material, texture: Option<Arc<Texture>>) -> Material {
+    // exporters usually leave a default Kd next to map_Kd, the texture alone gives the color then
+    let diffuse = if texture.is_some() { [1.0, 1.0, 1.0] } else { mtl.diffuse.unwrap_or([0.8, 0.8, 0.8]) };
+
+    // the PBR extension gives Pr directly, otherwise map the Blinn-Phong exponent onto a roughness
+    let roughness = match (parse_scalar(mtl, "Pr"), mtl.shininess) {
+        (Some(pr), _) => pr,
+        (None, Some(ns)) => (2.0 / (ns.max(0.0) + 2.0)).sqrt(),
+        (None, None) => 0.9,
+    };
+
+    // Ke is not part of the core mtl spec so tobj leaves it in the unknown params
+    let emission = mtl.unknown_param
+        .get("Ke")
+        .map(|value| parse_vec3(value))
+        .unwrap_or(Vec3::ZERO);
+
+    // illum 4, 6, 7 and 9 are the glass models, dissolve is a coverage for decals and cards
+    // and does not make anything refract
+    let transparent = matches!(mtl.illumination_model, Some(4 | 6 | 7 | 9));
+    if !transparent && mtl.dissolve.is_some_and(|d| d < 1.0) {
+        eprintln!("warning: material {} is partly dissolved, rendering it opaque", mtl.name);
+    }
+
+    if transparent {
+        // the transmission filter tints glass, Kd is usually black there
+        let filter = mtl.unknown_param
+            .get("Tf")
+            .map(|value| parse_vec3(value))
+            .unwrap_or(Vec3::ONE);
+
+        return Material {
+            color: filter * 255.0,
+            roughness: 0.0,
+            emission,
+            kind: MaterialKind::Dielectric {
+                ior: mtl.optical_density.unwrap_or(1.5),
+                absorption: Vec3::ZERO,
+            },
+            ..Default::default()
+        };
+    }
+
+    if let Some(kind) = parse_conductor(mtl) {
+        return Material {
+            color: Vec3::from(diffuse) * 255.0,
+            roughness,
+            emission,
+            kind,
+            ..Default::default()
+        };
+    }
+
+    let defaults = Material::default();
+    Material {
+        color: Vec3::from(diffuse) * 255.0,
+        color_texture: texture,
+        roughness,
+        emission,
+        kind: MaterialKind::Standard,
+        metallic: parse_scalar(mtl, "Pm").unwrap_or(defaults.metallic),
+        sheen: parse_scalar(mtl, "Ps").unwrap_or(defaults.sheen),
+        clearcoat: parse_scalar(mtl, "Pc").unwrap_or(defaults.clearcoat),
+        clearcoat_roughness: parse_scalar(mtl, "Pcr").unwrap_or(defaults.clearcoat_roughness),
+        anisotropic: parse_scalar(mtl, "aniso").unwrap_or(defaults.anisotropic),
+        ior: mtl.optical_density.unwrap_or(defaults.ior),
+        ..defaults
+    }
+}
+
+// Metals are not part of any mtl spec, these keys are our own:
+// "metal gold" (copper, aluminium, silver) picks a preset, "metal_eta" and "metal_k" give a measured
+// complex ior, "metal_color" with an optional "metal_edge" tint the artist friendly version of it
+fn parse_conductor(mtl: &tobj::Material) -> Option<MaterialKind> {
+    let param = |key: &str| mtl.unknown_param.get(key);
+
+    if let Some(name) = param("metal") {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "gold" => return Some(MaterialKind::GOLD),
+            "copper" => return Some(MaterialKind::COPPER),
+            "aluminium" | "aluminum" => return Some(MaterialKind::ALUMINIUM),
+            "silver" => return Some(MaterialKind::SILVER),
+            other => eprintln!("warning: unknown metal {other} in material {}", mtl.name),
+        }
+    }
+    if let (Some(eta), Some(k)) = (param("metal_eta"), param("metal_k")) {
+        return Some(MaterialKind::Conductor { eta: parse_vec3(eta), k: parse_vec3(k) });
+    }
+    if let Some(color) = param("metal_color") {
+        let edge = param("metal_edge").map_or(Vec3::ONE, |edge| parse_vec3(edge));
+        return Some(MaterialKind::conductor_from_reflectance(parse_vec3(color), edge));
+    }
+    None
+}
+
+// scalar parameters of the mtl PBR extension
+fn parse_scalar(mtl: &tobj::Material, key: &str) -> Option<f32> {
+    mtl.unknown_param.get(key)?.split_whitespace().next()?.parse().ok()
+}
+
+fn parse_vec3(value: &str) -> Vec3 {
+    let components: Vec<f32> = value
+        .split_whitespace()
+        .filter_map(|c| c.parse().ok())
+        .collect();
+
+    match components.as_slice() {
+        [r, g, b, ..] => Vec3::new(*r, *g, *b),
+        [v] => Vec3::splat(*v),
+        _ => Vec3::ZERO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mtl(dissolve: Option<f32>, illumination_model: Option<u8>) -> tobj::Material {
+        tobj::Material { name: "test".to_string(), dissolve, illumination_model, ..Default::default() }
+    }
+
+    const OBJ: &str = "\
+mtllib scene.mtl
+o quad
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+usemtl lamp
+f 1/1/1 2/2/1 3/3/1 4/4/1
+o tri
+usemtl gold
+f 1 2 3
+";
+
+    const MTL: &str = "\
+newmtl lamp
+Kd 1 0.5 0
+Ns 0
+Ke 2 2 1
+
+newmtl gold
+metal gold
+";
+
+    #[test]
+    fn round_trip() {
+        let directory = std::env::temp_dir().join(format!("ray_tracer_obj_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("scene.obj"), OBJ).unwrap();
+        std::fs::write(directory.join("scene.mtl"), MTL).unwrap();
+        let model = load_obj(directory.join("scene.obj"));
+        std::fs::remove_dir_all(&directory).unwrap();
+        let model = model.unwrap();
+
+        // the quad is split into two triangles and keeps its normals and uvs
+        let [quad, tri] = model.meshes.as_slice() else { panic!("expected two meshes") };
+        assert_eq!(quad.name, "quad");
+        assert_eq!(quad.vertices.len(), 4);
+        assert_eq!(quad.indices.len(), 2);
+        assert_eq!(quad.normals.as_deref(), Some([Vec3::Z; 4].as_slice()));
+        assert_eq!(quad.uvs.as_ref().unwrap()[2], Vec2::ONE);
+        assert_eq!(tri.indices.len(), 1);
+        assert!(tri.normals.is_none() && tri.uvs.is_none());
+
+        // colors are scaled to 0..255, Ke stays a linear radiance and Ns 0 is fully rough
+        let lamp = &model.materials[quad.material.unwrap()];
+        assert_eq!(lamp.color, Vec3::new(255.0, 127.5, 0.0));
+        assert_eq!(lamp.emission, Vec3::new(2.0, 2.0, 1.0));
+        assert_eq!(lamp.roughness, 1.0);
+        assert!(matches!(model.materials[tri.material.unwrap()].kind, MaterialKind::Conductor { .. }));
+    }
+
+    #[test]
+    fn missing_file() {
+        assert!(matches!(load_obj("does/not/exist.obj"), Err(LoadError::Io(_))));
+    }
+
+    #[test]
+    fn only_glass_illumination_models_refract() {
+        for material in [mtl(Some(0.99), None), mtl(Some(0.3), Some(2)), mtl(Some(1.0), None)] {
+            assert!(matches!(convert_material(&material, None).kind, MaterialKind::Standard));
+        }
+        for illum in [4, 6, 7, 9] {
+            assert!(matches!(convert_material(&mtl(None, Some(illum)), None).kind, MaterialKind::Dielectric { .. }));
+        }
+    }
+}
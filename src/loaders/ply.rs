@@ -0,0 +1,395 @@
+use std::path::Path;
+
+use glam::{Vec2, Vec3};
+
+use super::{LoadError, MeshData};
+
+// Reads ascii and binary (little and big endian) .ply files
+// polygons are fan triangulated, normals, uvs and vertex colors are kept when present
+pub fn load_ply(path: impl AsRef<Path>) -> Result<MeshData, LoadError> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+    let name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    parse_ply(&bytes, name)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+enum PropertyType {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+struct Property {
+    name: String,
+    kind: PropertyType,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+enum Value {
+    Scalar(f64),
+    List(Vec<f64>),
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<Self, LoadError> {
+        match name {
+            "char" | "int8" => Ok(ScalarType::I8),
+            "uchar" | "uint8" => Ok(ScalarType::U8),
+            "short" | "int16" => Ok(ScalarType::I16),
+            "ushort" | "uint16" => Ok(ScalarType::U16),
+            "int" | "int32" => Ok(ScalarType::I32),
+            "uint" | "uint32" => Ok(ScalarType::U32),
+            "float" | "float32" => Ok(ScalarType::F32),
+            "double" | "float64" => Ok(ScalarType::F64),
+            _ => Err(LoadError::Parse(format!("unknown ply type {name}"))),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    fn is_integer(self) -> bool {
+        !matches!(self, ScalarType::F32 | ScalarType::F64)
+    }
+}
+
+// Walks the data section, either as whitespace separated tokens or as raw bytes
+struct Reader<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    offset: usize,
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+}
+
+impl Reader<'_> {
+    fn read(&mut self, ty: ScalarType) -> Result<f64, LoadError> {
+        if self.format == Format::Ascii {
+            let token = self.tokens
+                .next()
+                .ok_or_else(|| LoadError::Parse("unexpected end of ply data".to_string()))?;
+            return token
+                .parse::<f64>()
+                .map_err(|_| LoadError::Parse(format!("invalid ply value {token}")));
+        }
+
+        let size = ty.size();
+        let end = self.offset + size;
+        if end > self.bytes.len() {
+            return Err(LoadError::Parse("unexpected end of ply data".to_string()));
+        }
+
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(&self.bytes[self.offset..end]);
+        if self.format == Format::BinaryBigEndian {
+            raw[..size].reverse();
+        }
+        self.offset = end;
+
+        Ok(match ty {
+            ScalarType::I8 => raw[0] as i8 as f64,
+            ScalarType::U8 => raw[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::U32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::F32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            ScalarType::F64 => f64::from_le_bytes(raw),
+        })
+    }
+
+    fn read_property(&mut self, kind: &PropertyType) -> Result<Value, LoadError> {
+        match kind {
+            PropertyType::Scalar(ty) => Ok(Value::Scalar(self.read(*ty)?)),
+            PropertyType::List { count, item } => {
+                let n = self.read(*count)? as usize;
+                let items = (0..n).map(|_| self.read(*item)).collect::<Result<_, _>>()?;
+                Ok(Value::List(items))
+            }
+        }
+    }
+}
+
+fn parse_header(bytes: &[u8]) -> Result<(Format, Vec<Element>, usize), LoadError> {
+    let end_marker = b"end_header";
+    let header_end = bytes
+        .windows(end_marker.len())
+        .position(|w| w == end_marker)
+        .ok_or_else(|| LoadError::Parse("missing ply end_header".to_string()))?;
+
+    // data starts after the newline that ends the end_header line
+    let mut data_start = header_end + end_marker.len();
+    while data_start < bytes.len() && bytes[data_start] != b'\n' {
+        data_start += 1;
+    }
+    data_start += 1;
+
+    let header = String::from_utf8_lossy(&bytes[..header_end]);
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err(LoadError::Parse("not a ply file".to_string()));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", kind, ..] => {
+                format = Some(match *kind {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(LoadError::Parse(format!("unknown ply format {kind}"))),
+                });
+            }
+            ["element", name, count] => {
+                let count = count
+                    .parse()
+                    .map_err(|_| LoadError::Parse(format!("invalid element count {count}")))?;
+                elements.push(Element { name: name.to_string(), count, properties: Vec::new() });
+            }
+            ["property", "list", count, item, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| LoadError::Parse("ply property before element".to_string()))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind: PropertyType::List { count: ScalarType::parse(count)?, item: ScalarType::parse(item)? },
+                });
+            }
+            ["property", ty, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| LoadError::Parse("ply property before element".to_string()))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind: PropertyType::Scalar(ScalarType::parse(ty)?),
+                });
+            }
+            _ => {} // comments, obj_info and blank lines
+        }
+    }
+
+    let format = format.ok_or_else(|| LoadError::Parse("missing ply format line".to_string()))?;
+    Ok((format, elements, data_start.min(bytes.len())))
+}
+
+fn parse_ply(bytes: &[u8], name: String) -> Result<MeshData, LoadError> {
+    let (format, elements, data_start) = parse_header(bytes)?;
+
+    let data = &bytes[data_start..];
+    let text = if format == Format::Ascii { std::str::from_utf8(data).unwrap_or("") } else { "" };
+    let mut reader = Reader { format, bytes: data, offset: 0, tokens: text.split_ascii_whitespace() };
+
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    let mut indices = Vec::new();
+
+    for element in elements.iter() {
+        let find = |names: &[&str]| element.properties.iter().position(|p| names.contains(&p.name.as_str()));
+
+        let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+        let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+        let uv = [find(&["u", "s", "texture_u", "texture_s"]), find(&["v", "t", "texture_v", "texture_t"])];
+        let color = [find(&["red", "r"]), find(&["green", "g"]), find(&["blue", "b"])];
+        let face = find(&["vertex_indices", "vertex_index"]);
+
+        // 8 bit colors are 0..255, float colors are 0..1
+        let color_scale = match color[0].map(|i| &element.properties[i].kind) {
+            Some(PropertyType::Scalar(ty)) if ty.is_integer() => 1.0,
+            _ => 255.0,
+        };
+
+        for _ in 0..element.count {
+            let values = element.properties
+                .iter()
+                .map(|p| reader.read_property(&p.kind))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let scalar = |index: Option<usize>| match index.map(|i| &values[i]) {
+                Some(Value::Scalar(v)) => Some(*v as f32),
+                _ => None,
+            };
+            let vec3 = |indices: [Option<usize>; 3]| {
+                Some(Vec3::new(scalar(indices[0])?, scalar(indices[1])?, scalar(indices[2])?))
+            };
+
+            if element.name == "vertex" {
+                vertices.push(vec3(position).unwrap_or(Vec3::ZERO));
+                if let Some(n) = vec3(normal) {
+                    normals.push(n.normalize_or_zero());
+                }
+                if let (Some(u), Some(v)) = (scalar(uv[0]), scalar(uv[1])) {
+                    uvs.push(Vec2::new(u, v));
+                }
+                if let Some(c) = vec3(color) {
+                    colors.push(c * color_scale);
+                }
+            } else if element.name == "face" {
+                if let Some(Value::List(polygon)) = face.map(|i| &values[i]) {
+                    for k in 1..polygon.len().saturating_sub(1) {
+                        indices.push([polygon[0] as u32, polygon[k] as u32, polygon[k + 1] as u32]);
+                    }
+                }
+            }
+        }
+    }
+
+    if indices.iter().flatten().any(|&i| i as usize >= vertices.len()) {
+        return Err(LoadError::Parse("ply face index out of range".to_string()));
+    }
+
+    // only keep attributes every vertex has
+    let complete = |len: usize| len == vertices.len() && len > 0;
+    Ok(MeshData {
+        name,
+        normals: complete(normals.len()).then_some(normals),
+        uvs: complete(uvs.len()).then_some(uvs),
+        colors: complete(colors.len()).then_some(colors),
+        vertices,
+        indices,
+        material: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER_END: &str = "element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+";
+
+    fn header(format: &str) -> String {
+        format!("ply\nformat {format} 1.0\ncomment test\n{HEADER_END}")
+    }
+
+    const POSITIONS: [[f32; 3]; 4] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]];
+
+    fn binary(format: &str, to_bytes: fn(f32) -> [u8; 4], index_bytes: fn(i32) -> [u8; 4]) -> Vec<u8> {
+        let mut bytes = header(format).into_bytes();
+        for (i, position) in POSITIONS.iter().enumerate() {
+            for &c in position {
+                bytes.extend_from_slice(&to_bytes(c));
+            }
+            bytes.extend_from_slice(&[255, 0, i as u8]);
+        }
+        bytes.push(4);
+        for i in 0..4 {
+            bytes.extend_from_slice(&index_bytes(i));
+        }
+        bytes
+    }
+
+    fn check_quad(mesh: &MeshData) {
+        let expected: Vec<Vec3> = POSITIONS.iter().map(|&p| Vec3::from(p)).collect();
+        assert_eq!(mesh.vertices, expected);
+        // the quad is fan triangulated
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.colors.as_ref().unwrap()[3], Vec3::new(255.0, 0.0, 3.0));
+        assert!(mesh.normals.is_none());
+    }
+
+    #[test]
+    fn ascii() {
+        let text = header("ascii") + "0 0 0 255 0 0\n1 0 0 255 0 1\n1 1 0 255 0 2\n0 1 0 255 0 3\n4 0 1 2 3\n";
+        check_quad(&parse_ply(text.as_bytes(), "test".to_string()).unwrap());
+    }
+
+    #[test]
+    fn binary_little_endian() {
+        let bytes = binary("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        check_quad(&parse_ply(&bytes, "test".to_string()).unwrap());
+    }
+
+    #[test]
+    fn binary_big_endian() {
+        let bytes = binary("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes);
+        check_quad(&parse_ply(&bytes, "test".to_string()).unwrap());
+    }
+
+    #[test]
+    fn float_normals_and_colors() {
+        let text = "ply
+format ascii 1.0
+element vertex 3
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property float red
+property float green
+property float blue
+element face 1
+property list uchar uint vertex_indices
+end_header
+0 0 0 0 0 2 1 0.5 0
+1 0 0 0 0 2 1 0.5 0
+0 1 0 0 0 2 1 0.5 0
+3 0 1 2
+";
+        let mesh = parse_ply(text.as_bytes(), "test".to_string()).unwrap();
+        assert_eq!(mesh.normals.unwrap(), vec![Vec3::Z; 3]);
+        // float colors are 0..1 and get scaled to 0..255
+        assert_eq!(mesh.colors.unwrap()[0], Vec3::new(255.0, 127.5, 0.0));
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = binary("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        for length in [bytes.len() - 1, bytes.len() - 20, header("binary_little_endian").len(), 20] {
+            assert!(matches!(parse_ply(&bytes[..length], "test".to_string()), Err(LoadError::Parse(_))));
+        }
+
+        let text = header("ascii") + "0 0 0 255 0 0\n1 0 0 255 0 1\n";
+        assert!(matches!(parse_ply(text.as_bytes(), "test".to_string()), Err(LoadError::Parse(_))));
+    }
+
+    #[test]
+    fn face_index_out_of_range() {
+        let text = header("ascii") + "0 0 0 255 0 0\n1 0 0 255 0 1\n1 1 0 255 0 2\n0 1 0 255 0 3\n3 0 1 9\n";
+        assert!(matches!(parse_ply(text.as_bytes(), "test".to_string()), Err(LoadError::Parse(_))));
+    }
+}
@@ -0,0 +1,174 @@
+use std::path::Path;
+
+use glam::Vec3;
+
+use super::{LoadError, MeshData};
+
+// Reads ascii and binary .stl files
+// STL stores unconnected triangles, so every corner gets its own vertex
+pub fn load_stl(path: impl AsRef<Path>) -> Result<MeshData, LoadError> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+    let name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    parse_stl(&bytes, name)
+}
+
+fn parse_stl(bytes: &[u8], name: String) -> Result<MeshData, LoadError> {
+    let vertices = if is_binary(bytes) {
+        parse_binary(bytes)?
+    } else {
+        parse_ascii(bytes)?
+    };
+
+    let indices = (0..vertices.len() as u32 / 3)
+        .map(|i| [i * 3, i * 3 + 1, i * 3 + 2])
+        .collect();
+
+    Ok(MeshData {
+        name,
+        vertices,
+        indices,
+        normals: None,
+        uvs: None,
+        colors: None,
+        material: None,
+    })
+}
+
+// Some binary exporters also start their header with "solid", so trust the size check first
+fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.len() >= 84 {
+        let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+        if bytes.len() == 84 + count * 50 {
+            return true;
+        }
+    }
+    !bytes.trim_ascii_start().starts_with(b"solid")
+}
+
+fn parse_binary(bytes: &[u8]) -> Result<Vec<Vec3>, LoadError> {
+    if bytes.len() < 84 {
+        return Err(LoadError::Parse("stl file too short".to_string()));
+    }
+
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    if bytes.len() < 84 + count * 50 {
+        return Err(LoadError::Parse("stl file truncated".to_string()));
+    }
+
+    let read_vec3 = |offset: usize| {
+        let f = |i: usize| {
+            let at = offset + i * 4;
+            f32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        Vec3::new(f(0), f(1), f(2))
+    };
+
+    let mut vertices = Vec::with_capacity(count * 3);
+    for i in 0..count {
+        // each record is a normal, three vertices and a 16 bit attribute
+        let record = 84 + i * 50;
+        vertices.push(read_vec3(record + 12));
+        vertices.push(read_vec3(record + 24));
+        vertices.push(read_vec3(record + 36));
+    }
+    Ok(vertices)
+}
+
+fn parse_ascii(bytes: &[u8]) -> Result<Vec<Vec3>, LoadError> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut vertices = Vec::new();
+
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("vertex") {
+            continue;
+        }
+
+        let coords: Vec<f32> = words.filter_map(|w| w.parse().ok()).collect();
+        match coords.as_slice() {
+            [x, y, z] => vertices.push(Vec3::new(*x, *y, *z)),
+            _ => return Err(LoadError::Parse(format!("invalid stl vertex line: {line}"))),
+        }
+    }
+
+    if vertices.len() % 3 != 0 {
+        return Err(LoadError::Parse("stl facet with missing vertices".to_string()));
+    }
+    Ok(vertices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary_stl(header: &[u8], count: u32, triangles: &[[Vec3; 3]]) -> Vec<u8> {
+        let mut bytes = header.to_vec();
+        bytes.resize(80, 0);
+        bytes.extend_from_slice(&count.to_le_bytes());
+        for triangle in triangles {
+            bytes.extend_from_slice(&[0u8; 12]);
+            for v in triangle {
+                for c in v.to_array() {
+                    bytes.extend_from_slice(&c.to_le_bytes());
+                }
+            }
+            bytes.extend_from_slice(&[0u8; 2]);
+        }
+        bytes
+    }
+
+    const TRIANGLES: [[Vec3; 3]; 2] = [
+        [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)],
+        [Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)],
+    ];
+
+    #[test]
+    fn ascii() {
+        let text = "solid test
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid test
+";
+        let mesh = parse_stl(text.as_bytes(), "test".to_string()).unwrap();
+        assert_eq!(mesh.vertices, TRIANGLES[0].to_vec());
+        assert_eq!(mesh.indices, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn binary() {
+        let mesh = parse_stl(&binary_stl(b"binary", 2, &TRIANGLES), "test".to_string()).unwrap();
+        assert_eq!(mesh.vertices, TRIANGLES.concat());
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [3, 4, 5]]);
+    }
+
+    #[test]
+    fn binary_with_solid_header() {
+        // the size matches the triangle count, so it is not mistaken for ascii
+        let mesh = parse_stl(&binary_stl(b"solid exported", 2, &TRIANGLES), "test".to_string()).unwrap();
+        assert_eq!(mesh.indices.len(), 2);
+    }
+
+    #[test]
+    fn truncated_binary() {
+        let mut bytes = binary_stl(b"binary", 2, &TRIANGLES);
+        bytes.truncate(bytes.len() - 10);
+        assert!(matches!(parse_stl(&bytes, "test".to_string()), Err(LoadError::Parse(_))));
+
+        // a count far beyond the file length
+        let bytes = binary_stl(b"binary", u32::MAX, &TRIANGLES);
+        assert!(matches!(parse_stl(&bytes, "test".to_string()), Err(LoadError::Parse(_))));
+        assert!(matches!(parse_stl(&bytes[..40], "test".to_string()), Err(LoadError::Parse(_))));
+    }
+
+    #[test]
+    fn truncated_ascii() {
+        let text = "solid test\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\n";
+        assert!(matches!(parse_stl(text.as_bytes(), "test".to_string()), Err(LoadError::Parse(_))));
+    }
+}
@@ -0,0 +1,83 @@
+use std::io::Cursor;
+use std::path::Path;
+
+use glam::{Vec3, Vec4};
+
+use crate::texture::Texture;
+use crate::tonemap::srgb_eotf;
+use super::LoadError;
+
+// Reads a .png color texture, the sRGB encoded values are turned back into linear ones
+// other image formats are reported as parse errors
+pub fn load_texture(path: impl AsRef<Path>) -> Result<Texture, LoadError> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path)?;
+    decode_texture(&bytes).map_err(|err| match err {
+        LoadError::Parse(msg) => LoadError::Parse(format!("{}: {msg}", path.display())),
+        err => err,
+    })
+}
+
+pub fn decode_texture(bytes: &[u8]) -> Result<Texture, LoadError> {
+    let (width, height, pixels) = decode_png(bytes)?;
+    let pixels = pixels.into_iter().map(|rgba| srgb_eotf(rgba.truncate())).collect();
+    Ok(Texture { width, height, pixels })
+}
+
+// Any png as rgba in 0..1, gray images are spread over the color channels and missing alpha is 1
+pub fn decode_png(bytes: &[u8]) -> Result<(usize, usize, Vec<Vec4>), LoadError> {
+    let parse_error = |err: png::DecodingError| LoadError::Parse(err.to_string());
+
+    // jpeg and friends are common in model files, say so instead of a signature error
+    if !bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        let format = match bytes {
+            [0xff, 0xd8, 0xff, ..] => "jpeg",
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "webp",
+            [b'B', b'M', ..] => "bmp",
+            [b'G', b'I', b'F', b'8', ..] => "gif",
+            _ => "unknown format",
+        };
+        return Err(LoadError::Parse(format!("only png images are supported, this is {format}")));
+    }
+
+    let mut decoder = png::Decoder::new(Cursor::new(bytes));
+    // palettes and 16 bit channels become plain 8 bit gray or rgb
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(parse_error)?;
+
+    let size = reader.output_buffer_size().ok_or_else(|| LoadError::Parse("png is too large".to_string()))?;
+    let mut buffer = vec![0u8; size];
+    let info = reader.next_frame(&mut buffer).map_err(parse_error)?;
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let channels = info.color_type.samples();
+    let pixels = (0..height)
+        .flat_map(|y| buffer[y * info.line_size..].chunks_exact(channels).take(width))
+        .map(|pixel| {
+            let value = |i: usize| pixel[i] as f32 / 255.0;
+            match pixel.len() {
+                1 => Vec3::splat(value(0)).extend(1.0),
+                2 => Vec3::splat(value(0)).extend(value(1)),
+                3 => Vec4::new(value(0), value(1), value(2), 1.0),
+                _ => Vec4::new(value(0), value(1), value(2), value(3)),
+            }
+        })
+        .collect();
+
+    Ok((width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn other_formats_are_named() {
+        let jpeg = [0xff, 0xd8, 0xff, 0xe0, 0, 0x10, b'J', b'F', b'I', b'F'];
+        match decode_texture(&jpeg) {
+            Err(LoadError::Parse(msg)) => assert!(msg.contains("only png") && msg.contains("jpeg"), "{msg}"),
+            _ => panic!("a jpeg is not a png"),
+        }
+        assert!(matches!(decode_png(b"not an image"), Err(LoadError::Parse(_))));
+    }
+}
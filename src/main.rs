@@ -1,130 +1,60 @@
-use glfw::{Context};
-use glow::HasContext;
 use glam::Vec3;
 
 mod renderer;
+mod bvh;
+mod bsdf;
+mod environment;
 mod primitives;
 mod lights;
+mod sky;
+mod loaders;
+mod cli;
+mod aov;
+mod denoise;
+mod framebuffer;
+mod output;
+mod tonemap;
+mod texture;
+#[cfg(feature = "window")]
+mod window;
+
+use primitives::primitives::{Material, Primitives};
 
 fn main() {
-    let screen_width: i32 = 800;
-    let screen_height: i32 = 800;
-
-    let max_pixel_average = 150;
-
-    let mut glfw = glfw::init(glfw::fail_on_errors).unwrap();
-    glfw.window_hint(glfw::WindowHint::ContextVersionMajor(4));
-    glfw.window_hint(glfw::WindowHint::ContextVersionMinor(6));
-    glfw.window_hint(glfw::WindowHint::OpenGlProfile(glfw::OpenGlProfileHint::Core));
-
-    let (mut window, _) = glfw
-        .create_window(screen_width as u32, screen_height as u32, "RAY_TRACER", glfw::WindowMode::Windowed)
-        .expect("Failed to create window");
-    window.make_current();
-    window.set_resizable(false);
-    glfw.set_swap_interval(glfw::SwapInterval::None);
-
-    let gl = unsafe { 
-        glow::Context::from_loader_function(|s| { 
-            match window.get_proc_address(s) { 
-                Some(p) => std::mem::transmute::<unsafe extern "C" fn(), 
-                *const std::ffi::c_void>(p), None => std::ptr::null(), 
-            } 
-        }) 
-    };
-    unsafe {
-        gl.viewport(0, 0, screen_width, screen_height);
-        gl.clear_color(0.0, 0.0, 0.0, 1.0);
-    }
-
-    let program = unsafe {
-        let vs = gl.create_shader(glow::VERTEX_SHADER).unwrap();
-        gl.shader_source(vs, VERTEX_SHADER);
-        gl.compile_shader(vs);
-        if !gl.get_shader_compile_status(vs) {
-            panic!("Vertex shader compile error: {}", gl.get_shader_info_log(vs));
-        }
-
-        let fs = gl.create_shader(glow::FRAGMENT_SHADER).unwrap();
-        gl.shader_source(fs, PIXEL_SHADER);
-        gl.compile_shader(fs);
-        if !gl.get_shader_compile_status(fs) {
-            panic!("Fragment shader compile error: {}", gl.get_shader_info_log(fs));
-        }
-
-        let prog = gl.create_program().unwrap();
-        gl.attach_shader(prog, vs);
-        gl.attach_shader(prog, fs);
-        gl.link_program(prog);
-        if !gl.get_program_link_status(prog) {
-            panic!("Shader link error: {}", gl.get_program_info_log(prog));
+    let options = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{msg}\n\n{}", cli::USAGE);
+            std::process::exit(2);
         }
-
-        gl.delete_shader(vs);
-        gl.delete_shader(fs);
-
-        prog
     };
-
-    let vertices: [f32; 20] = [
-        -1.0, -1.0, 0.0, 0.0, 0.0,
-         1.0, -1.0, 0.0, 1.0, 0.0,
-         1.0,  1.0, 0.0, 1.0, 1.0,
-        -1.0,  1.0, 0.0, 0.0, 1.0,
-    ];
-    let indices: [u32; 6] = [0, 1, 2, 2, 3, 0];
-
-    let vao = unsafe { gl.create_vertex_array().unwrap() };
-    let vbo = unsafe { gl.create_buffer().unwrap() };
-    let ebo = unsafe { gl.create_buffer().unwrap() };
-
-    unsafe {
-        gl.bind_vertex_array(Some(vao));
-
-        gl.bind_buffer(glow::ARRAY_BUFFER, Some(vbo));
-        gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, bytemuck::cast_slice(&vertices), glow::STATIC_DRAW);
-
-        gl.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(ebo));
-        gl.buffer_data_u8_slice(glow::ELEMENT_ARRAY_BUFFER, bytemuck::cast_slice(&indices), glow::STATIC_DRAW);
-
-        let stride = (5 * std::mem::size_of::<f32>()) as i32;
-        gl.enable_vertex_attrib_array(0);
-        gl.vertex_attrib_pointer_f32(0, 3, glow::FLOAT, false, stride, 0);
-        gl.enable_vertex_attrib_array(1);
-        gl.vertex_attrib_pointer_f32(1, 2, glow::FLOAT, false, stride, (3 * std::mem::size_of::<f32>()) as i32);
+    if options.help {
+        println!("{}", cli::USAGE);
+        return;
     }
 
-    // make texture
-    let texture = unsafe{ gl.create_texture().unwrap()};
-    unsafe {
-        gl.bind_texture(glow::TEXTURE_2D, Some(texture));
-        gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::NEAREST as i32);
-        gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::NEAREST as i32);
-
-        gl.tex_image_2d(
-            glow::TEXTURE_2D,
-            0,
-            glow::RGBA8 as i32,
-            screen_width,
-            screen_height,
-            0,
-            glow::RGBA,
-            glow::UNSIGNED_BYTE,
-            None,
-        );
+    let result = match &options.scene {
+        Some(path) => render_scene_file(&options, path),
+        None => render_demo_scene(&options),
+    };
+    if let Err(msg) = result {
+        eprintln!("error: {msg}");
+        std::process::exit(1);
     }
-    
-
-    let buffer_size = (screen_width * screen_height * 4) as i32;
-    let pbo = unsafe { gl.create_buffer().unwrap() };
-    let map_flags = glow::MAP_WRITE_BIT | glow::MAP_PERSISTENT_BIT | glow::MAP_COHERENT_BIT;
+}
 
+fn render_demo_scene(options: &cli::Options) -> Result<(), String> {
+    // +y is up, images come out with their first row at the top
     let main_camera = renderer::Camera {
         position: Vec3::new(0.0, 0.0, 2.0),
         look_at: Vec3::new(0.0, 0.0, 0.0),
         up: Vec3::new(0.0, 1.0, 0.0),
         fov: 90.0,
         background_color: Vec3::new(0.0, 0.0, 0.0),
+        aperture_radius: 0.0,
+        focus_distance: 2.0,
+        aperture_shape: renderer::ApertureShape::Circle,
+        projection: renderer::Projection::Perspective,
     };
 
     let sphere_one = primitives::primitives::Sphere {
@@ -134,26 +64,29 @@ fn main() {
             color: Vec3::new(200.0, 20.0, 200.0),
             roughness: 0.7,
             emission: Vec3::new(0.0, 0.0, 0.0),
+            ..Default::default()
         }     
     };
 
     let sphere_two = primitives::primitives::Sphere {
-        center: Vec3::new(0.9, 0.5, 0.3),
+        center: Vec3::new(0.9, -0.5, 0.3),
         radius: 0.5 ,
         material: &primitives::primitives::Material {
             color: Vec3::new(255.0, 255.0, 255.0) ,
             roughness: 0.70,
             emission: Vec3::new(0.0, 0.0, 0.0),
+            ..Default::default()
         }       
     };
 
     let ground_plane = primitives::primitives::Plane {
-        point: Vec3::new(0.0, 1.0, 0.0),    
-        normal: Vec3::new(0.0, -1.0, 0.0),
+        point: Vec3::new(0.0, -1.0, 0.0),
+        normal: Vec3::new(0.0, 1.0, 0.0),
         material: &primitives::primitives::Material {
             color: Vec3::new(255.0, 255.0, 255.0) ,
             roughness: 0.9,
             emission: Vec3::new(0.0, 0.0, 0.0),
+            ..Default::default()
         }   
     };
 
@@ -164,123 +97,205 @@ fn main() {
             color: Vec3::new(255.0, 255.0, 255.0) ,
             roughness: 0.9,
             emission: Vec3::new(0.0, 0.0, 0.0),
+            ..Default::default()
         }   
     };
 
+    let triangle = primitives::primitives::Triangle {
+        v0: Vec3::new(-2.6, -1.0, -1.0),
+        v1: Vec3::new(-1.2, -1.0, -1.5),
+        v2: Vec3::new(-2.0, 0.8, -1.7),
+        material: &primitives::primitives::Material {
+            color: Vec3::new(40.0, 160.0, 220.0),
+            roughness: 0.4,
+            ..Default::default()
+        }
+    };
+
     let point_light = lights::lights::Light::Point(lights::lights::PointLight {
-        position: Vec3::new(-2.0, -4.0, 4.0),
-        intensity: 0.4,
+        position: Vec3::new(-2.0, 4.0, 4.0),
+        intensity: 60.0,
         color: Vec3::new(200.0, 200.0, 200.0),
     });
 
 
-    let render_scene = renderer::Scene {
-        objects: vec![&sphere_one, &ground_plane, &wall_plane, &sphere_two],
-        lights: vec![&point_light],
-    };
-
-    unsafe {
-        gl.bind_buffer(glow::PIXEL_UNPACK_BUFFER, Some(pbo));
-        gl.buffer_storage(glow::PIXEL_UNPACK_BUFFER, buffer_size, None, map_flags);
-
-        let ptr = gl.map_buffer_range(glow::PIXEL_UNPACK_BUFFER, 0, buffer_size, map_flags) as *mut u8;
-        if ptr.is_null() {
-            panic!("Failed to map PBO persistently!");
-        }
-
-        gl.use_program(Some(program));
-        if let Some(loc) = gl.get_uniform_location(program, "uTexture") {
-            gl.uniform_1_i32(Some(&loc), 0); // bind to texture unit 0
-        }
-        gl.active_texture(glow::TEXTURE0);
-        gl.bind_texture(glow::TEXTURE_2D, Some(texture));
-        gl.bind_vertex_array(Some(vao));
-        gl.bind_buffer(glow::PIXEL_UNPACK_BUFFER, Some(pbo));
-
-        let w = screen_width as usize;
-        let h = screen_height as usize;
-
-        let pixel_count = screen_width * screen_height;
-        let u32_ptr = ptr as *mut u32; // Cast byte-pointer to u32-pointer
+    let mut render_scene = renderer::Scene::new(
+        vec![&sphere_one, &ground_plane, &wall_plane, &sphere_two, &triangle],
+        vec![&point_light],
+    );
 
-        // LLVM perfers to have this inside the loop? It logicaly only need to happen once before the loop
-        // But compiler optimizes much more aggresivly when its in the loop?!?! (short-lived alias)
-        let slice = std::slice::from_raw_parts_mut(u32_ptr, pixel_count as usize);
+    render_scene.max_depth = options.max_depth;
+    render_scene.environment = load_environment(options)?;
 
-        for y in 0..h {
-            let row_start = y * w;
-            let row = &mut slice[row_start..row_start + w];
+    present(options, main_camera, &render_scene)
+}
 
-            for x in 0..w {
-                let mut total_pixel_color = Vec3::ZERO;
-                for _ in 0..max_pixel_average {
-                    let pixel_color = renderer::render_function(x, y, screen_width, screen_height, &main_camera, &render_scene);
-                    total_pixel_color += pixel_color;
-                }
+// Renders a model file, lit by its own lights and emitters or a default sky when it has neither
+// and no environment was given
+fn render_scene_file(options: &cli::Options, path: &std::path::Path) -> Result<(), String> {
+    let loaders::SceneFile { model, cameras, lights } = loaders::load_scene(path).map_err(|err| err.to_string())?;
 
-                total_pixel_color /= max_pixel_average as f32;
+    let fallback = Material { color: Vec3::splat(200.0), roughness: 0.7, ..Default::default() };
+    let meshes = loaders::build_meshes(model.meshes, &model.materials, &fallback);
 
-                row[x] = pack_color(total_pixel_color);
+    let mut render_scene = renderer::Scene::new(
+        meshes.iter().map(|mesh| mesh as &dyn Primitives).collect(),
+        lights.iter().collect(),
+    );
+    // emissive meshes light the scene just as well, a cornell box must not get the sky on top
+    let emissive = meshes.iter().any(|mesh| mesh.material.emission.max_element() > 0.0);
+    render_scene.environment = load_environment(options)?;
+    if render_scene.environment.is_none() && lights.is_empty() && !emissive {
+        render_scene.environment = Some(environment::Environment::Sky(build_sky(options)));
+    }
+    render_scene.max_depth = options.max_depth;
+
+    // use the first camera of the file, otherwise look at the whole model from the front
+    let camera = match cameras.into_iter().next() {
+        Some(camera) => camera,
+        None => {
+            let bounds = meshes.iter().filter_map(|mesh| mesh.bounds()).reduce(|a, b| a.union(b));
+            let (center, radius) = bounds.map_or((Vec3::ZERO, 1.0), |b| (b.centroid(), (b.max - b.min).length() * 0.5));
+            let fov: f32 = 45.0;
+            let distance = radius.max(1e-3) / (fov.to_radians() * 0.5).sin();
+            renderer::Camera {
+                position: center + Vec3::new(0.0, 0.0, distance),
+                look_at: center,
+                up: Vec3::Y,
+                fov,
+                background_color: Vec3::ZERO,
+                aperture_radius: 0.0,
+                focus_distance: distance,
+                aperture_shape: renderer::ApertureShape::Circle,
+                projection: renderer::Projection::Perspective,
             }
         }
+    };
 
-        gl.tex_sub_image_2d(
-            glow::TEXTURE_2D,
-            0,
-            0,
-            0,
-            screen_width,
-            screen_height,
-            glow::RGBA,
-            glow::UNSIGNED_BYTE,
-            glow::PixelUnpackData::BufferOffset(0),
-        );
+    present(options, camera, &render_scene)
+}
 
-        // Draw quad
-        gl.draw_elements(glow::TRIANGLES, 6, glow::UNSIGNED_INT, 0);
+// The environment asked for on the command line, if any
+fn load_environment(options: &cli::Options) -> Result<Option<environment::Environment>, String> {
+    let sun_options = options.sun_elevation.is_some() || options.sun_azimuth.is_some();
+    let location_options = options.latitude.is_some()
+        || options.longitude.is_some()
+        || options.day_of_year.is_some()
+        || options.utc_time.is_some();
+    let sky_options = sun_options || location_options || options.turbidity.is_some() || options.sky_intensity.is_some();
+
+    if sun_options && location_options {
+        return Err("--sun-elevation and --sun-azimuth cannot be combined with --latitude, --longitude, --day or --time".to_string());
+    }
+    let Some(path) = &options.environment_map else {
+        return Ok(sky_options.then(|| environment::Environment::Sky(build_sky(options))));
+    };
+    if sky_options {
+        return Err("--env cannot be combined with the sky options".to_string());
+    }
+    let image = loaders::hdr::load_hdr(path).map_err(|err| err.to_string())?;
+    let map = environment::EnvironmentMap::new(image, options.env_intensity, options.env_rotation);
+    Ok(Some(environment::Environment::Map(map)))
+}
 
-        window.swap_buffers();
+// Daylight from the sky options, either a sun position or a place and time on earth
+fn build_sky(options: &cli::Options) -> sky::Sky {
+    let turbidity = options.turbidity.unwrap_or(3.0);
+    let intensity = options.sky_intensity.unwrap_or(1.0);
+
+    let location = options.latitude.is_some()
+        || options.longitude.is_some()
+        || options.day_of_year.is_some()
+        || options.utc_time.is_some();
+    if !location {
+        return sky::Sky::new(options.sun_elevation.unwrap_or(45.0), options.sun_azimuth.unwrap_or(135.0), turbidity, intensity);
+    }
 
-        while !window.should_close() {
-            glfw.poll_events();
-        }
-    }   
+    // defaults to noon at the june solstice on the equator
+    let sky = sky::Sky::from_location(
+        options.latitude.unwrap_or(0.0),
+        options.longitude.unwrap_or(0.0),
+        options.day_of_year.unwrap_or(172),
+        options.utc_time.unwrap_or(12.0),
+        turbidity,
+        intensity,
+    );
+    if sky.sun_direction.y <= 0.0 {
+        eprintln!("warning: the sun is below the horizon at that place and time, the sky stays as it is at sunset");
+    }
+    sky
 }
 
-fn pack_color(c: Vec3) -> u32 {
-    let gamma = 2.2;
-    // gamma correction
-    let corrected = Vec3::new(
-        c.x.clamp(0.0, 1.0).powf(1.0 / gamma),
-        c.y.clamp(0.0, 1.0).powf(1.0 / gamma),
-        c.z.clamp(0.0, 1.0).powf(1.0 / gamma),
-    );
+// Writes the image when an output file was given, otherwise shows it in a window
+fn present(options: &cli::Options, mut camera: renderer::Camera, scene: &renderer::Scene) -> Result<(), String> {
+    apply_camera_options(&mut camera, options)?;
+    let camera = &camera;
 
-    let r = (corrected.x * 255.0) as u8;
-    let g = (corrected.y * 255.0) as u8;
-    let b = (corrected.z * 255.0) as u8;
-    let a = 255u8;
+    let Some(output_path) = &options.output else {
+        #[cfg(feature = "window")]
+        {
+            window::run(camera, scene, options.width as i32, options.height as i32, options.samples, options.denoise, &options.display);
+            return Ok(());
+        }
+        #[cfg(not(feature = "window"))]
+        return Err("built without the window feature, pass --output to render to a file".to_string());
+    };
 
-    ((a as u32) << 24) | ((b as u32) << 16) | ((g as u32) << 8) | (r as u32)
+    // the denoiser needs the passes as guides even when they are not saved
+    let mut image = renderer::render_image(camera, scene, options.width, options.height, options.samples, options.aovs || options.denoise);
+    if options.denoise {
+        denoise::denoise(&mut image)?;
+    }
+    if !options.aovs {
+        image.layers.clear();
+    }
+    let save_options = output::SaveOptions { exr_pixel_type: options.exr_pixel_type, display: options.display };
+    output::write_image(output_path, &image, &save_options).map_err(|err| err.to_string())
 }
 
-const VERTEX_SHADER: &str = r#"
-#version 460 core
-layout (location = 0) in vec3 aPos;
-layout (location = 1) in vec2 aTexCoord;
-out vec2 TexCoord;
-void main() {
-    gl_Position = vec4(aPos, 1.0);
-    TexCoord = aTexCoord;
-}
-"#;
+// Projection and lens settings from the command line replace the ones of the demo or scene camera
+fn apply_camera_options(camera: &mut renderer::Camera, options: &cli::Options) -> Result<(), String> {
+    if let Some(fov) = options.fov {
+        // the parser already checked the fov against a projection given on the command line
+        let perspective = matches!(camera.projection, renderer::Projection::Perspective);
+        if options.projection.is_none() && perspective && !cli::ProjectionKind::Perspective.accepts_fov(fov) {
+            return Err(format!("--fov has to stay below 180 degrees for a perspective camera, got {fov}"));
+        }
+        camera.fov = fov;
+    }
+    if let Some(radius) = options.aperture_radius {
+        camera.aperture_radius = radius;
+    }
+    if let Some(distance) = options.focus_distance {
+        camera.focus_distance = distance;
+    }
 
-const PIXEL_SHADER: &str = r#"
-#version 460 core
-in vec2 TexCoord;
-out vec4 FragColor;
-uniform sampler2D uTexture;
-void main() {
-    FragColor = texture(uTexture, TexCoord);
+    if let Some(kind) = options.projection {
+        let eye_separation = options.eye_separation;
+        camera.projection = match kind {
+            cli::ProjectionKind::Perspective => renderer::Projection::Perspective,
+            cli::ProjectionKind::Orthographic => {
+                // without an explicit height keep the framing the perspective camera has at the focus plane
+                let height = options.ortho_height.unwrap_or_else(|| match camera.projection {
+                    renderer::Projection::Orthographic { height } => height,
+                    _ => 2.0 * camera.focus_distance * (camera.fov.min(179.0).to_radians() * 0.5).tan(),
+                });
+                renderer::Projection::Orthographic { height }
+            }
+            cli::ProjectionKind::Fisheye => renderer::Projection::Fisheye,
+            cli::ProjectionKind::Equirectangular => renderer::Projection::Equirectangular,
+            cli::ProjectionKind::Stereo => renderer::Projection::Stereo { eye_separation, panoramic: false },
+            cli::ProjectionKind::StereoEquirectangular => renderer::Projection::Stereo { eye_separation, panoramic: true },
+        };
+    } else if let (Some(height), renderer::Projection::Orthographic { .. }) = (options.ortho_height, &camera.projection) {
+        camera.projection = renderer::Projection::Orthographic { height };
+    }
+
+    if let Some(path) = &options.aperture_mask {
+        let mask = loaders::mask::load_aperture_mask(path).map_err(|err| err.to_string())?;
+        camera.aperture_shape = renderer::ApertureShape::Mask(mask);
+    } else if let Some(blades) = options.blades {
+        camera.aperture_shape = renderer::ApertureShape::Polygon { blades, rotation: options.blade_rotation };
+    }
+    Ok(())
 }
-"#;
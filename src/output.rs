@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::framebuffer::Framebuffer;
+use crate::tonemap::DisplayTransform;
+
+pub mod exr;
+pub mod hdr;
+
+pub use exr::ExrPixelType;
+
+// How images are stored, each format uses the settings that apply to it
+#[derive(Clone, Copy, Debug)]
+pub struct SaveOptions {
+    pub exr_pixel_type: ExrPixelType,
+    // tone mapping for the 8 bit formats, the float formats stay scene linear
+    pub display: DisplayTransform,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Encode(String),
+}
+
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::Io(err) => write!(f, "io error: {err}"),
+            SaveError::Encode(msg) => write!(f, "encode error: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<std::io::Error> for SaveError {
+    fn from(err: std::io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+// Writes the image in the format given by the extension, .exr and .hdr keep the linear floats,
+// .png and .ppm are display referred 8 bit, only .exr stores the extra layers
+pub fn write_image(path: impl AsRef<Path>, image: &Framebuffer, options: &SaveOptions) -> Result<(), SaveError> {
+    let path = path.as_ref();
+    let (width, height) = (image.width, image.height);
+    if image.color.len() != width * height {
+        return Err(SaveError::Encode(format!("expected {} pixels, got {}", width * height, image.color.len())));
+    }
+
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    let encode_8bit = || -> Vec<u8> { image.color.iter().flat_map(|&c| options.display.encode(c)).collect() };
+
+    match extension.as_str() {
+        "exr" => exr::write_exr(path, image, options.exr_pixel_type),
+        "hdr" => hdr::write_hdr(path, width, height, &image.color),
+        "ppm" => write_ppm(path, width, height, &encode_8bit()),
+        "png" => write_png(path, width, height, &encode_8bit()),
+        _ => Err(SaveError::Encode(format!("unsupported image format {}", path.display()))),
+    }
+}
+
+// binary P6 with a 255 maximum
+fn write_ppm(path: &Path, width: usize, height: usize, rgb: &[u8]) -> Result<(), SaveError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P6\n{width} {height}\n255\n")?;
+    file.write_all(rgb)?;
+    file.flush()?;
+    Ok(())
+}
+
+fn write_png(path: &Path, width: usize, height: usize, rgb: &[u8]) -> Result<(), SaveError> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let convert = |err: png::EncodingError| SaveError::Encode(err.to_string());
+    let mut writer = encoder.write_header().map_err(convert)?;
+    writer.write_image_data(rgb).map_err(convert)?;
+    writer.finish().map_err(convert)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    // 3 x 2 image with a red, a gray and an overexposed pixel in its first row
+    fn image() -> Framebuffer {
+        let mut image = Framebuffer::new(3, 2);
+        image.color[0] = Vec3::X;
+        image.color[1] = Vec3::splat(0.5);
+        image.color[2] = Vec3::splat(4.0);
+        image
+    }
+
+    fn save(name: &str) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("ray_tracer_output_{}_{name}", std::process::id()));
+        let options = SaveOptions { exr_pixel_type: ExrPixelType::Half, display: DisplayTransform::default() };
+        let result = write_image(&path, &image(), &options);
+        let bytes = std::fs::read(&path);
+        let _ = std::fs::remove_file(&path);
+        result.unwrap();
+        bytes.unwrap()
+    }
+
+    #[test]
+    fn ppm_header_and_pixels() {
+        let bytes = save("image.ppm");
+        let header = b"P6\n3 2\n255\n";
+        assert!(bytes.starts_with(header));
+        assert_eq!(bytes.len(), header.len() + 3 * 2 * 3);
+        // srgb encoded and clamped, rows from the top
+        let pixels = &bytes[header.len()..];
+        assert_eq!(&pixels[..9], &[255, 0, 0, 188, 188, 188, 255, 255, 255]);
+        assert!(pixels[9..].iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn png_header_and_pixels() {
+        let bytes = save("image.png");
+        assert!(bytes.starts_with(b"\x89PNG\r\n\x1a\n"));
+        // IHDR comes first, width and height big endian, 8 bit rgb
+        assert_eq!(&bytes[12..16], b"IHDR");
+        assert_eq!(&bytes[16..24], &[0, 0, 0, 3, 0, 0, 0, 2]);
+        assert_eq!(&bytes[24..26], &[8, 2]);
+
+        let (width, height, pixels) = crate::loaders::texture::decode_png(&bytes).unwrap();
+        assert_eq!((width, height), (3, 2));
+        // the same srgb encoded bytes as the ppm
+        assert_eq!(pixels[0].truncate(), Vec3::X);
+        assert_eq!(pixels[1].x, 188.0 / 255.0);
+        assert_eq!(pixels[2].truncate(), Vec3::ONE);
+    }
+
+    #[test]
+    fn unknown_extension() {
+        let options = SaveOptions { exr_pixel_type: ExrPixelType::Half, display: DisplayTransform::default() };
+        let result = write_image(std::env::temp_dir().join("image.tiff"), &image(), &options);
+        assert!(matches!(result, Err(SaveError::Encode(msg)) if msg.contains("unsupported image format")));
+
+        let mut broken = image();
+        broken.color.pop();
+        assert!(matches!(write_image("image.ppm", &broken, &options), Err(SaveError::Encode(_))));
+    }
+}
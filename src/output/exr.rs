@@ -0,0 +1,277 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use glam::Vec3;
+
+use crate::framebuffer::Framebuffer;
+use super::SaveError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExrPixelType {
+    Half,
+    Float,
+}
+
+// One channel as stored in the file, name is the full "layer.channel" name
+struct Channel<'a> {
+    name: String,
+    pixels: &'a [Vec3],
+    component: usize,
+}
+
+// Single part scanline OpenEXR without compression, the color goes into R, G and B
+// and every extra layer into "layer.channel" channels next to it
+pub fn write_exr(path: &Path, image: &Framebuffer, pixel_type: ExrPixelType) -> Result<(), SaveError> {
+    let mut channels: Vec<Channel> = ["R", "G", "B"]
+        .iter()
+        .enumerate()
+        .map(|(component, name)| Channel { name: name.to_string(), pixels: &image.color, component })
+        .collect();
+
+    for layer in image.layers.iter() {
+        if layer.pixels.len() != image.width * image.height {
+            return Err(SaveError::Encode(format!("layer {} has the wrong size", layer.name)));
+        }
+        for (component, channel) in layer.channels.iter().enumerate().take(3) {
+            channels.push(Channel { name: format!("{}.{channel}", layer.name), pixels: &layer.pixels, component });
+        }
+    }
+
+    // readers expect the channel list, and with it the pixel data, sorted by name
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    if channels.windows(2).any(|pair| pair[0].name == pair[1].name) {
+        return Err(SaveError::Encode("duplicate exr channel names".to_string()));
+    }
+
+    let (type_id, bytes_per_value) = match pixel_type {
+        ExrPixelType::Half => (1i32, 2),
+        ExrPixelType::Float => (2i32, 4),
+    };
+
+    let mut header = Vec::new();
+    // magic number and version 2, single part scanline
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01]);
+    header.extend_from_slice(&2u32.to_le_bytes());
+
+    let mut channel_list = Vec::new();
+    for channel in channels.iter() {
+        channel_list.extend_from_slice(channel.name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&type_id.to_le_bytes());
+        // linear flag and three reserved bytes
+        channel_list.extend_from_slice(&[0, 0, 0, 0]);
+        // x and y sampling
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    write_attribute(&mut header, "channels", "chlist", &channel_list);
+
+    // no compression
+    write_attribute(&mut header, "compression", "compression", &[0]);
+
+    let window = [0i32, 0, image.width as i32 - 1, image.height as i32 - 1];
+    let window_bytes: Vec<u8> = window.iter().flat_map(|v| v.to_le_bytes()).collect();
+    write_attribute(&mut header, "dataWindow", "box2i", &window_bytes);
+    write_attribute(&mut header, "displayWindow", "box2i", &window_bytes);
+
+    // rows from top to bottom
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    write_attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0u8; 8]);
+    write_attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    header.push(0);
+
+    // offset table, one entry per scanline pointing at its block
+    let line_size = image.width * channels.len() * bytes_per_value;
+    let block_size = 8 + line_size;
+    let first_block = header.len() + image.height * 8;
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&header)?;
+    for y in 0..image.height {
+        file.write_all(&((first_block + y * block_size) as u64).to_le_bytes())?;
+    }
+
+    // each block is the y coordinate, the data size and then every channel of the line in turn
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..image.height {
+        line.clear();
+        for channel in channels.iter() {
+            for pixel in &channel.pixels[y * image.width..(y + 1) * image.width] {
+                let value = pixel[channel.component];
+                match pixel_type {
+                    ExrPixelType::Half => line.extend_from_slice(&f32_to_half(value).to_le_bytes()),
+                    ExrPixelType::Float => line.extend_from_slice(&value.to_le_bytes()),
+                }
+            }
+        }
+        file.write_all(&(y as i32).to_le_bytes())?;
+        file.write_all(&(line.len() as i32).to_le_bytes())?;
+        file.write_all(&line)?;
+    }
+
+    file.flush()?;
+    Ok(())
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+// IEEE 754 binary16 with round to nearest even, out of range values become infinity
+fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    // infinity and nan, keep nan a nan
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    if half_exponent <= 0 {
+        // subnormal half or zero
+        if half_exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let half_mantissa = mantissa >> shift;
+        let remainder = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round_up = remainder > halfway || (remainder == halfway && half_mantissa & 1 == 1);
+        return sign | (half_mantissa + round_up as u32) as u16;
+    }
+
+    let half = ((half_exponent as u32) << 10) | (mantissa >> 13);
+    let remainder = mantissa & 0x1fff;
+    let round_up = remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1);
+    // a carry out of the mantissa correctly bumps the exponent, up to infinity
+    sign | (half + round_up as u32) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Attributes of the header as (name, type, value), and the offset right after it
+    fn read_header(bytes: &[u8]) -> (Vec<(String, String, Vec<u8>)>, usize) {
+        let mut attributes = Vec::new();
+        let mut at = 8;
+        let string = |at: &mut usize| {
+            let end = *at + bytes[*at..].iter().position(|&b| b == 0).unwrap();
+            let text = String::from_utf8(bytes[*at..end].to_vec()).unwrap();
+            *at = end + 1;
+            text
+        };
+        while bytes[at] != 0 {
+            let name = string(&mut at);
+            let kind = string(&mut at);
+            let size = i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as usize;
+            attributes.push((name, kind, bytes[at + 4..at + 4 + size].to_vec()));
+            at += 4 + size;
+        }
+        (attributes, at + 1)
+    }
+
+    #[test]
+    fn header_and_scanlines() {
+        let mut image = Framebuffer::new(2, 3);
+        image.color[1] = Vec3::new(0.25, 0.5, 2.0);
+        image.layers.push(crate::framebuffer::Layer { name: "depth".to_string(), channels: &["Z"], pixels: vec![Vec3::splat(7.0); 6] });
+
+        let path = std::env::temp_dir().join(format!("ray_tracer_exr_{}.exr", std::process::id()));
+        let result = write_exr(&path, &image, ExrPixelType::Float);
+        let bytes = std::fs::read(&path);
+        let _ = std::fs::remove_file(&path);
+        result.unwrap();
+        let bytes = bytes.unwrap();
+
+        assert_eq!(&bytes[..8], &[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+        let (attributes, end) = read_header(&bytes);
+        let attribute = |name: &str| attributes.iter().find(|(n, _, _)| n == name).unwrap();
+        for required in ["channels", "compression", "dataWindow", "displayWindow", "lineOrder", "pixelAspectRatio", "screenWindowCenter", "screenWindowWidth"] {
+            assert!(attributes.iter().any(|(name, _, _)| name == required), "{required}");
+        }
+
+        // channel names sorted, each followed by its type, flags and sampling
+        let (_, kind, channels) = attribute("channels");
+        assert_eq!(kind, "chlist");
+        let mut names = Vec::new();
+        let mut at = 0;
+        while channels[at] != 0 {
+            let end = at + channels[at..].iter().position(|&b| b == 0).unwrap();
+            names.push(std::str::from_utf8(&channels[at..end]).unwrap());
+            // float pixels, not linear, sampled every pixel
+            assert_eq!(&channels[end + 1..end + 17], &[2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0]);
+            at = end + 17;
+        }
+        assert_eq!(names, ["B", "G", "R", "depth.Z"]);
+        assert_eq!(at + 1, channels.len());
+        let window: Vec<u8> = [0i32, 0, 1, 2].iter().flat_map(|v| v.to_le_bytes()).collect();
+        assert_eq!(attribute("dataWindow").2, window);
+
+        // the offset table points at one block per line, starting with its y
+        let offset = |y: usize| u64::from_le_bytes(bytes[end + y * 8..end + y * 8 + 8].try_into().unwrap()) as usize;
+        let line_size = 2 * 4 * 4;
+        assert_eq!(offset(0), end + 3 * 8);
+        assert_eq!(bytes.len(), offset(2) + 8 + line_size);
+        for y in 0..3 {
+            let block = offset(y);
+            assert_eq!(i32::from_le_bytes(bytes[block..block + 4].try_into().unwrap()), y as i32);
+            assert_eq!(i32::from_le_bytes(bytes[block + 4..block + 8].try_into().unwrap()), line_size as i32);
+        }
+
+        // first line, channel by channel: B of both pixels, then G, R and depth
+        let values: Vec<f32> = bytes[offset(0) + 8..offset(0) + 8 + line_size].chunks(4).map(|v| f32::from_le_bytes(v.try_into().unwrap())).collect();
+        assert_eq!(values, [0.0, 2.0, 0.0, 0.5, 0.0, 0.25, 7.0, 7.0]);
+    }
+
+    #[test]
+    fn half_conversion() {
+        assert_eq!(f32_to_half(0.0), 0x0000);
+        assert_eq!(f32_to_half(-0.0), 0x8000);
+        assert_eq!(f32_to_half(1.0), 0x3c00);
+        assert_eq!(f32_to_half(-2.0), 0xc000);
+        assert_eq!(f32_to_half(0.1), 0x2e66);
+        assert_eq!(f32_to_half(65504.0), 0x7bff);
+        // ties go to the even mantissa
+        assert_eq!(f32_to_half(1.0 + 2.0f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_half(1.0 + 3.0 * 2.0f32.powi(-11)), 0x3c02);
+    }
+
+    #[test]
+    fn half_subnormals() {
+        assert_eq!(f32_to_half(2.0f32.powi(-14)), 0x0400);
+        assert_eq!(f32_to_half(1023.0 * 2.0f32.powi(-24)), 0x03ff);
+        assert_eq!(f32_to_half(2.0f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_half(-2.0f32.powi(-24)), 0x8001);
+        assert_eq!(f32_to_half(2.0f32.powi(-25)), 0x0000);
+        assert_eq!(f32_to_half(3.0 * 2.0f32.powi(-26)), 0x0001);
+        assert_eq!(f32_to_half(1e-10), 0x0000);
+    }
+
+    #[test]
+    fn half_out_of_range() {
+        assert_eq!(f32_to_half(65520.0), 0x7c00);
+        assert_eq!(f32_to_half(1e10), 0x7c00);
+        assert_eq!(f32_to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_half(f32::NEG_INFINITY), 0xfc00);
+        let nan = f32_to_half(f32::NAN);
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x03ff, 0);
+    }
+}
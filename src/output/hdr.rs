@@ -0,0 +1,139 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use glam::Vec3;
+
+use super::SaveError;
+
+// Radiance .hdr (RGBE) with run length encoded scanlines where the format allows it
+pub fn write_hdr(path: &Path, width: usize, height: usize, pixels: &[Vec3]) -> Result<(), SaveError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    encode_hdr(&mut file, width, height, pixels)?;
+    file.flush()?;
+    Ok(())
+}
+
+fn encode_hdr(out: &mut impl Write, width: usize, height: usize, pixels: &[Vec3]) -> std::io::Result<()> {
+    write!(out, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {height} +X {width}\n")?;
+
+    let mut scanline = Vec::with_capacity(width);
+    let mut encoded = Vec::new();
+    for row in pixels.chunks_exact(width.max(1)).take(height) {
+        scanline.clear();
+        scanline.extend(row.iter().map(|&c| rgb_to_rgbe(c)));
+
+        encoded.clear();
+        // the rle marker stores the width in 15 bits and short lines are always flat
+        if (8..0x8000).contains(&width) {
+            encoded.extend_from_slice(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8]);
+            for channel in 0..4 {
+                let values: Vec<u8> = scanline.iter().map(|rgbe| rgbe[channel]).collect();
+                encode_channel(&values, &mut encoded);
+            }
+        } else {
+            encoded.extend(scanline.iter().flatten());
+        }
+        out.write_all(&encoded)?;
+    }
+    Ok(())
+}
+
+// Runs of at least 4 equal values become (128 + count, value), everything else literal chunks
+fn encode_channel(values: &[u8], out: &mut Vec<u8>) {
+    const MIN_RUN: usize = 4;
+    let mut start = 0;
+
+    while start < values.len() {
+        // find the next run long enough to be worth encoding
+        let mut run_start = start;
+        let mut run_length = 0;
+        while run_start < values.len() {
+            run_length = values[run_start..].iter().take(127).take_while(|&&v| v == values[run_start]).count();
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += 1;
+        }
+        if run_length < MIN_RUN {
+            run_start = values.len();
+        }
+
+        // literals up to the run, at most 128 at a time
+        for chunk in values[start..run_start].chunks(128) {
+            out.push(chunk.len() as u8);
+            out.extend_from_slice(chunk);
+        }
+
+        if run_start < values.len() {
+            out.push(128 + run_length as u8);
+            out.push(values[run_start]);
+            start = run_start + run_length;
+        } else {
+            start = values.len();
+        }
+    }
+}
+
+// shared exponent encoding, the loader adds half a step back when decoding
+fn rgb_to_rgbe(c: Vec3) -> [u8; 4] {
+    let c = c.max(Vec3::ZERO);
+    let max = c.max_element();
+    if !max.is_finite() || max < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    // max = mantissa * 2^exponent with the mantissa in [0.5, 1)
+    let exponent = max.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2.0f32.powi(exponent);
+    let encode = |v: f32| (v * scale).min(255.0) as u8;
+    [encode(c.x), encode(c.y), encode(c.z), (exponent + 128).clamp(0, 255) as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loaders::hdr::parse_hdr;
+
+    fn round_trip(width: usize, height: usize, pixels: &[Vec3]) -> Vec<Vec3> {
+        let mut bytes = Vec::new();
+        encode_hdr(&mut bytes, width, height, pixels).unwrap();
+        let image = parse_hdr(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (width, height));
+        image.pixels
+    }
+
+    // the shared exponent keeps 8 bits relative to the brightest channel
+    fn assert_close(decoded: &[Vec3], pixels: &[Vec3]) {
+        for (d, p) in decoded.iter().zip(pixels) {
+            let tolerance = p.max_element() / 128.0;
+            assert!((*d - *p).abs().max_element() <= tolerance, "{d} != {p}");
+        }
+    }
+
+    #[test]
+    fn run_length_encoded_round_trip() {
+        // runs, literals and a run longer than 127 in one scanline
+        let mut pixels = vec![Vec3::new(1.0, 0.5, 0.25); 140];
+        pixels.extend((0..60).map(|i| Vec3::new(i as f32 * 0.37, 1000.0 / (i + 1) as f32, 1e-3 * i as f32)));
+        pixels.extend(vec![Vec3::splat(3.0); 200]);
+        let decoded = round_trip(200, 2, &pixels);
+        assert_close(&decoded, &pixels);
+    }
+
+    #[test]
+    fn flat_round_trip() {
+        let pixels = [Vec3::new(0.18, 0.18, 0.18), Vec3::new(5e4, 2.0, 0.0), Vec3::new(1e-5, 2e-5, 4e-5)];
+        let decoded = round_trip(3, 1, &pixels);
+        assert_close(&decoded, &pixels);
+    }
+
+    #[test]
+    fn black_and_invalid_colors() {
+        let pixels = [Vec3::ZERO, Vec3::splat(-1.0), Vec3::splat(f32::INFINITY), Vec3::new(f32::NAN, 1.0, 1.0)];
+        let decoded = round_trip(4, 1, &pixels);
+        assert_eq!(decoded[..3], [Vec3::ZERO; 3]);
+        // only the nan channel is lost
+        assert_close(&decoded[3..], &[Vec3::new(0.0, 1.0, 1.0)]);
+    }
+}
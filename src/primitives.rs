@@ -1,10 +1,104 @@
-
+#[allow(clippy::module_inception)]
 pub mod primitives {
-    use glam::Vec3;
+    use std::sync::Arc;
+
+    use glam::{Vec2, Vec3};
+    use rand::prelude::*;
+
+    use crate::bvh::{Aabb, Bvh};
+    use crate::texture::Texture;
 
     pub trait Primitives {
-        fn intersection(&self, ray_origin: Vec3, ray_direction: Vec3) -> Option<(f32, Vec3)>;
+        fn intersection(&self, ray_origin: Vec3, ray_direction: Vec3) -> Option<HitRecord>;
         fn get_material(&self) -> &Material;
+        // None for unbounded primitives, those are tested outside the scene bvh
+        fn bounds(&self) -> Option<Aabb>;
+
+        // Any hit closer than max_distance, shadow rays do not need the closest one
+        fn occluded(&self, ray_origin: Vec3, ray_direction: Vec3, max_distance: f32) -> bool {
+            self.intersection(ray_origin, ray_direction)
+                .is_some_and(|hit| hit.t < max_distance)
+        }
+
+        // Random point on the surface for light sampling, as seen from reference
+        // None for shapes that cannot be sampled, emissive ones are then only found by bounces
+        fn sample_point(&self, _reference: Vec3, _rng: &mut ThreadRng) -> Option<SurfaceSample> {
+            None
+        }
+
+        // Area density of sample_point returning position
+        fn sample_pdf(&self, _reference: Vec3, _position: Vec3) -> f32 {
+            0.0
+        }
+    }
+
+    // Point picked on an emissive surface, pdf is per unit area
+    #[derive(Clone, Copy, Debug)]
+    pub struct SurfaceSample {
+        pub position: Vec3,
+        pub normal: Vec3,
+        pub pdf: f32,
+    }
+
+    // Everything the renderer needs to know about a ray hit
+    // both normals face the side the ray arrived from, front_face tells if that was the outside
+    #[derive(Clone, Copy, Debug)]
+    pub struct HitRecord {
+        pub t: f32,
+        pub position: Vec3,
+        pub geometric_normal: Vec3,
+        pub shading_normal: Vec3,
+        pub front_face: bool,
+        pub uv: Vec2,
+        // orthonormal with shading_normal, tangent follows increasing u where the surface has uvs
+        pub tangent: Vec3,
+        pub bitangent: Vec3,
+        // per vertex color in the 0..255 range, replaces the material color when present
+        pub vertex_color: Option<Vec3>,
+        // triangle index inside a mesh, 0 for single primitives
+        pub primitive_id: u32,
+        // index into Scene::objects, filled in by the scene
+        pub instance_id: u32,
+    }
+
+    impl HitRecord {
+        // Builds a record from outward facing normals and flips them towards the ray
+        // dpdu is the surface derivative along u, zero when the primitive has no natural one
+        pub fn new(
+            ray_dir: Vec3,
+            t: f32,
+            position: Vec3,
+            outward_normal: Vec3,
+            outward_shading_normal: Vec3,
+            uv: Vec2,
+            dpdu: Vec3
+        ) -> Self {
+            let front_face = ray_dir.dot(outward_normal) < 0.0;
+            let (geometric_normal, shading_normal) = if front_face {
+                (outward_normal, outward_shading_normal)
+            } else {
+                (-outward_normal, -outward_shading_normal)
+            };
+
+            // Gram-Schmidt the surface derivative against the shading normal
+            let projected = dpdu - shading_normal * shading_normal.dot(dpdu);
+            let tangent = projected.try_normalize().unwrap_or_else(|| shading_normal.any_orthonormal_vector());
+            let bitangent = shading_normal.cross(tangent);
+
+            HitRecord {
+                t,
+                position,
+                geometric_normal,
+                shading_normal,
+                front_face,
+                uv,
+                tangent,
+                bitangent,
+                vertex_color: None,
+                primitive_id: 0,
+                instance_id: 0,
+            }
+        }
     }
     pub struct Sphere <'a> {
         pub center: Vec3,
@@ -18,15 +112,141 @@ pub mod primitives {
         pub material: &'a Material
     }
 
-    #[derive(Default)]
+    pub struct Triangle <'a> {
+        pub v0: Vec3,
+        pub v1: Vec3,
+        pub v2: Vec3,
+        pub material: &'a Material
+    }
+
+    // Indexed triangles over a shared vertex buffer
+    // normals, uvs and colors are optional, when present they hold one entry per vertex
+    pub struct TriangleMesh <'a> {
+        pub vertices: Vec<Vec3>,
+        pub indices: Vec<[u32; 3]>,
+        pub normals: Option<Vec<Vec3>>,
+        pub uvs: Option<Vec<Vec2>>,
+        pub colors: Option<Vec<Vec3>>,
+        pub material: &'a Material,
+        bvh: Bvh,
+        // running triangle areas for picking triangles by area
+        area_cdf: Vec<f32>,
+    }
+
     pub struct Material {
+        // base color in 0..255
         pub color: Vec3,
+        // multiplies the color at the hit uv
+        pub color_texture: Option<Arc<Texture>>,
         pub roughness: f32,
+        // linear radiance, unlike the color it is not scaled to 0..255
         pub emission: Vec3,
+        pub kind: MaterialKind,
+
+        // principled parameters, only read by MaterialKind::Standard, all in 0..1
+        pub metallic: f32,
+        // 0.5 is a 4% reflectance at normal incidence
+        pub specular: f32,
+        pub specular_tint: f32,
+        pub anisotropic: f32,
+        pub sheen: f32,
+        pub sheen_tint: f32,
+        pub clearcoat: f32,
+        pub clearcoat_roughness: f32,
+        pub transmission: f32,
+        pub ior: f32,
+        pub subsurface: f32,
+    }
+
+    impl Default for Material {
+        fn default() -> Self {
+            Material {
+                color: Vec3::ZERO,
+                color_texture: None,
+                roughness: 0.0,
+                emission: Vec3::ZERO,
+                kind: MaterialKind::Standard,
+                metallic: 0.0,
+                specular: 0.5,
+                specular_tint: 0.0,
+                anisotropic: 0.0,
+                sheen: 0.0,
+                sheen_tint: 0.5,
+                clearcoat: 0.0,
+                clearcoat_roughness: 0.03,
+                transmission: 0.0,
+                ior: 1.5,
+                subsurface: 0.0,
+            }
+        }
+    }
+
+    impl Material {
+        // Surface color at the hit in 0..255, vertex colors replace the material color
+        // and the texture tints whichever of the two is used
+        pub fn base_color(&self, hit: &HitRecord) -> Vec3 {
+            let color = hit.vertex_color.unwrap_or(self.color);
+            match &self.color_texture {
+                Some(texture) => color * texture.sample(hit.uv),
+                None => color,
+            }
+        }
+    }
+
+    #[derive(Default, Clone, Copy)]
+    pub enum MaterialKind {
+        // principled uber material driven by the Material parameters
+        #[default]
+        Standard,
+        // smooth glass / water, color tints the transmitted light
+        // absorption is the Beer-Lambert coefficient per unit of distance inside the object
+        Dielectric { ior: f32, absorption: Vec3 },
+        // metal with a per channel complex index of refraction eta + i k
+        // the color comes from the fresnel term alone, roughness still drives the GGX lobe
+        Conductor { eta: Vec3, k: Vec3 },
+    }
+
+    impl MaterialKind {
+        // rgb fits of measured spectral data at roughly 650, 550 and 450 nm
+        pub const GOLD: MaterialKind = MaterialKind::Conductor {
+            eta: Vec3::new(0.143, 0.374, 1.442),
+            k: Vec3::new(3.983, 2.385, 1.603),
+        };
+        pub const COPPER: MaterialKind = MaterialKind::Conductor {
+            eta: Vec3::new(0.200, 0.924, 1.102),
+            k: Vec3::new(3.912, 2.452, 2.142),
+        };
+        pub const ALUMINIUM: MaterialKind = MaterialKind::Conductor {
+            eta: Vec3::new(1.657, 0.880, 0.521),
+            k: Vec3::new(9.224, 6.270, 4.837),
+        };
+        pub const SILVER: MaterialKind = MaterialKind::Conductor {
+            eta: Vec3::new(0.155, 0.117, 0.138),
+            k: Vec3::new(4.828, 3.122, 2.147),
+        };
+
+        // Artist friendly metal from its color at normal incidence and towards grazing angles,
+        // mapped onto eta and k after Gulbrandsen 2014
+        pub fn conductor_from_reflectance(reflectance: Vec3, edge_tint: Vec3) -> MaterialKind {
+            let r = reflectance.clamp(Vec3::ZERO, Vec3::splat(0.99));
+            let g = edge_tint.clamp(Vec3::ZERO, Vec3::ONE);
+
+            let sqrt_r = r.powf(0.5);
+            let eta = g * (Vec3::ONE - r) / (Vec3::ONE + r)
+                + (Vec3::ONE - g) * (Vec3::ONE + sqrt_r) / (Vec3::ONE - sqrt_r);
+            let k2 = (r * (eta + Vec3::ONE).powf(2.0) - (eta - Vec3::ONE).powf(2.0)) / (Vec3::ONE - r);
+
+            MaterialKind::Conductor { eta, k: k2.max(Vec3::ZERO).powf(0.5) }
+        }
+
+        // perfectly specular surfaces get no direct light from point sources
+        pub fn is_specular(&self) -> bool {
+            matches!(self, MaterialKind::Dielectric { .. })
+        }
     }
     
     impl Primitives for Sphere <'_>{ // not sure why it needs an unspesified lifetime
-        fn intersection(&self, ray_origin: Vec3, ray_dir: Vec3) -> Option<(f32, Vec3)> {
+        fn intersection(&self, ray_origin: Vec3, ray_dir: Vec3) -> Option<HitRecord> {
             let oc = ray_origin - self.center;
     
             let a = ray_dir.dot(ray_dir);                      
@@ -58,17 +278,56 @@ pub mod primitives {
     
             let hit_point = ray_origin + ray_dir * t;
             let normal = (hit_point - self.center).normalize();
-    
-            Some((t, normal))
+
+            // longitude / latitude around the y axis
+            let phi = normal.z.atan2(normal.x);
+            let theta = normal.y.clamp(-1.0, 1.0).acos();
+            let uv = Vec2::new(
+                (phi + std::f32::consts::PI) / (2.0 * std::f32::consts::PI),
+                theta / std::f32::consts::PI,
+            );
+            let dpdu = Vec3::new(-normal.z, 0.0, normal.x);
+
+            Some(HitRecord::new(ray_dir, t, hit_point, normal, normal, uv, dpdu))
         }
 
         fn get_material(&self) -> & Material {
-            &self.material
+            self.material
+        }
+
+        fn bounds(&self) -> Option<Aabb> {
+            let extent = Vec3::splat(self.radius.abs());
+            Some(Aabb { min: self.center - extent, max: self.center + extent })
+        }
+
+        // only the half facing the reference can be visible, so sample that hemisphere
+        fn sample_point(&self, reference: Vec3, rng: &mut ThreadRng) -> Option<SurfaceSample> {
+            let axis = (reference - self.center).try_normalize()?;
+            let radius = self.radius.abs();
+
+            let z: f32 = rng.random();
+            let phi = 2.0 * std::f32::consts::PI * rng.random::<f32>();
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let (u_axis, v_axis) = axis.any_orthonormal_pair();
+            let normal = axis * z + (u_axis * phi.cos() + v_axis * phi.sin()) * r;
+
+            Some(SurfaceSample {
+                position: self.center + normal * radius,
+                normal,
+                pdf: 1.0 / (2.0 * std::f32::consts::PI * radius * radius),
+            })
+        }
+
+        fn sample_pdf(&self, reference: Vec3, position: Vec3) -> f32 {
+            if (position - self.center).dot(reference - self.center) <= 0.0 {
+                return 0.0;
+            }
+            1.0 / (2.0 * std::f32::consts::PI * self.radius * self.radius)
         }
     }
     
     impl Primitives for Plane <'_>{
-        fn intersection(&self, ray_origin: Vec3, ray_dir: Vec3) -> Option<(f32, Vec3)> {
+        fn intersection(&self, ray_origin: Vec3, ray_dir: Vec3) -> Option<HitRecord> {
             let denom = ray_dir.dot(self.normal);
     
             // Ray parallel to plane?
@@ -81,12 +340,389 @@ pub mod primitives {
             if t < 0.001 {
                 return None; // behind ray or too close
             }
-    
-            Some((t, self.normal))
+
+            // uvs are world units along two axes in the plane
+            let hit_point = ray_origin + ray_dir * t;
+            let (u_axis, v_axis) = self.normal.any_orthonormal_pair();
+            let offset = hit_point - self.point;
+            let uv = Vec2::new(offset.dot(u_axis), offset.dot(v_axis));
+
+            Some(HitRecord::new(ray_dir, t, hit_point, self.normal, self.normal, uv, u_axis))
+        }
+
+        fn get_material(&self) -> &Material {
+            self.material
+        }
+
+        fn bounds(&self) -> Option<Aabb> {
+            None
+        }
+    }
+
+    impl Primitives for Triangle <'_>{
+        fn intersection(&self, ray_origin: Vec3, ray_dir: Vec3) -> Option<HitRecord> {
+            let (t, barycentric) = intersect_triangle(ray_origin, ray_dir, self.v0, self.v1, self.v2)?;
+
+            let normal = (self.v1 - self.v0).cross(self.v2 - self.v0).normalize();
+
+            // uv (0, 0), (1, 0) and (0, 1) at the corners
+            let uv = Vec2::new(barycentric.y, barycentric.z);
+            let hit_point = ray_origin + ray_dir * t;
+
+            Some(HitRecord::new(ray_dir, t, hit_point, normal, normal, uv, self.v1 - self.v0))
+        }
+
+        fn get_material(&self) -> &Material {
+            self.material
+        }
+
+        fn bounds(&self) -> Option<Aabb> {
+            Some(Aabb::from_points(&[self.v0, self.v1, self.v2]))
+        }
+
+        fn sample_point(&self, _reference: Vec3, rng: &mut ThreadRng) -> Option<SurfaceSample> {
+            sample_triangle(self.v0, self.v1, self.v2, rng)
+        }
+
+        fn sample_pdf(&self, _reference: Vec3, _position: Vec3) -> f32 {
+            let area = 0.5 * (self.v1 - self.v0).cross(self.v2 - self.v0).length();
+            if area > 0.0 { 1.0 / area } else { 0.0 }
+        }
+    }
+
+    impl <'a> TriangleMesh <'a> {
+        pub fn new(
+            vertices: Vec<Vec3>,
+            indices: Vec<[u32; 3]>,
+            normals: Option<Vec<Vec3>>,
+            uvs: Option<Vec<Vec2>>,
+            material: &'a Material
+        ) -> Self {
+            let mut mesh = TriangleMesh { vertices, indices, normals, uvs, colors: None, material, bvh: Bvh::build(&[]), area_cdf: Vec::new() };
+            mesh.build_bvh();
+            mesh
+        }
+
+        // Needs to be called again after changing vertices or indices
+        pub fn build_bvh(&mut self) {
+            let bounds: Vec<Aabb> = self.indices
+                .iter()
+                .map(|tri| Aabb::from_points(&self.triangle(tri)))
+                .collect();
+            self.bvh = Bvh::build(&bounds);
+
+            let mut total = 0.0;
+            self.area_cdf = self.indices
+                .iter()
+                .map(|tri| {
+                    let [p0, p1, p2] = self.triangle(tri);
+                    total += 0.5 * (p1 - p0).cross(p2 - p0).length();
+                    total
+                })
+                .collect();
+        }
+
+        // Area weighted vertex normals for meshes that come without any
+        pub fn compute_normals(&mut self) {
+            let mut normals = vec![Vec3::ZERO; self.vertices.len()];
+
+            for tri in self.indices.iter() {
+                let [p0, p1, p2] = self.triangle(tri);
+                // the cross product length is twice the area, so larger faces weigh more
+                let face_normal = (p1 - p0).cross(p2 - p0);
+                for &i in tri.iter() {
+                    normals[i as usize] += face_normal;
+                }
+            }
+
+            for n in normals.iter_mut() {
+                *n = n.normalize_or_zero();
+            }
+            self.normals = Some(normals);
+        }
+
+        pub fn triangle(&self, tri: &[u32; 3]) -> [Vec3; 3] {
+            [
+                self.vertices[tri[0] as usize],
+                self.vertices[tri[1] as usize],
+                self.vertices[tri[2] as usize],
+            ]
+        }
+    }
+
+    impl Primitives for TriangleMesh <'_>{
+        fn intersection(&self, ray_origin: Vec3, ray_dir: Vec3) -> Option<HitRecord> {
+            let mut closest: Option<(f32, usize, Vec3)> = None;
+
+            self.bvh.traverse(ray_origin, ray_dir, f32::INFINITY, |index| {
+                let [p0, p1, p2] = self.triangle(&self.indices[index]);
+                let (t, barycentric) = intersect_triangle(ray_origin, ray_dir, p0, p1, p2)?;
+                if closest.is_none_or(|(closest_t, _, _)| t < closest_t) {
+                    closest = Some((t, index, barycentric));
+                }
+                Some(t)
+            });
+
+            let (t, index, barycentric) = closest?;
+            let tri = &self.indices[index];
+            let [p0, p1, p2] = self.triangle(tri);
+            let geometric_normal = (p1 - p0).cross(p2 - p0).normalize();
+
+            // interpolate vertex normals when the mesh has them
+            let shading_normal = match &self.normals {
+                Some(normals) => interpolate(normals, tri, barycentric).normalize_or(geometric_normal),
+                None => geometric_normal,
+            };
+
+            let (uv, dpdu) = match &self.uvs {
+                Some(uvs) => {
+                    let [uv0, uv1, uv2] = [uvs[tri[0] as usize], uvs[tri[1] as usize], uvs[tri[2] as usize]];
+                    let uv = uv0 * barycentric.x + uv1 * barycentric.y + uv2 * barycentric.z;
+
+                    // solve for the position derivative along u from the two triangle edges
+                    let (duv1, duv2) = (uv1 - uv0, uv2 - uv0);
+                    let det = duv1.x * duv2.y - duv1.y * duv2.x;
+                    let dpdu = if det.abs() > 1e-12 {
+                        ((p1 - p0) * duv2.y - (p2 - p0) * duv1.y) / det
+                    } else {
+                        p1 - p0
+                    };
+                    (uv, dpdu)
+                }
+                None => (Vec2::new(barycentric.y, barycentric.z), p1 - p0),
+            };
+
+            let hit_point = ray_origin + ray_dir * t;
+            let mut hit = HitRecord::new(ray_dir, t, hit_point, geometric_normal, shading_normal, uv, dpdu);
+            hit.primitive_id = index as u32;
+            hit.vertex_color = self.colors.as_ref().map(|colors| interpolate(colors, tri, barycentric));
+
+            Some(hit)
         }
 
         fn get_material(&self) -> &Material {
-            &self.material
+            self.material
+        }
+
+        fn bounds(&self) -> Option<Aabb> {
+            if self.vertices.is_empty() {
+                return None;
+            }
+            Some(Aabb::from_points(&self.vertices))
+        }
+
+        fn occluded(&self, ray_origin: Vec3, ray_dir: Vec3, max_distance: f32) -> bool {
+            self.bvh.any_hit(ray_origin, ray_dir, max_distance, |index| {
+                let [p0, p1, p2] = self.triangle(&self.indices[index]);
+                intersect_triangle(ray_origin, ray_dir, p0, p1, p2).is_some_and(|(t, _)| t < max_distance)
+            })
+        }
+
+        // triangles are picked by area, so the whole surface is sampled uniformly
+        fn sample_point(&self, _reference: Vec3, rng: &mut ThreadRng) -> Option<SurfaceSample> {
+            let total = *self.area_cdf.last()?;
+            if total <= 0.0 {
+                return None;
+            }
+
+            let target = rng.random::<f32>() * total;
+            let index = self.area_cdf.partition_point(|&area| area <= target).min(self.indices.len() - 1);
+            let [p0, p1, p2] = self.triangle(&self.indices[index]);
+
+            let sample = sample_triangle(p0, p1, p2, rng)?;
+            Some(SurfaceSample { pdf: 1.0 / total, ..sample })
+        }
+
+        fn sample_pdf(&self, _reference: Vec3, _position: Vec3) -> f32 {
+            match self.area_cdf.last() {
+                Some(&total) if total > 0.0 => 1.0 / total,
+                _ => 0.0,
+            }
+        }
+    }
+
+    // Uniform point on a triangle
+    fn sample_triangle(p0: Vec3, p1: Vec3, p2: Vec3, rng: &mut ThreadRng) -> Option<SurfaceSample> {
+        let cross = (p1 - p0).cross(p2 - p0);
+        let area = 0.5 * cross.length();
+        if area <= 0.0 {
+            return None;
+        }
+
+        let mut s: f32 = rng.random();
+        let mut t: f32 = rng.random();
+        if s + t > 1.0 {
+            s = 1.0 - s;
+            t = 1.0 - t;
+        }
+
+        Some(SurfaceSample {
+            position: p0 + (p1 - p0) * s + (p2 - p0) * t,
+            normal: cross / (2.0 * area),
+            pdf: 1.0 / area,
+        })
+    }
+
+    fn interpolate(values: &[Vec3], tri: &[u32; 3], barycentric: Vec3) -> Vec3 {
+        values[tri[0] as usize] * barycentric.x
+            + values[tri[1] as usize] * barycentric.y
+            + values[tri[2] as usize] * barycentric.z
+    }
+
+    // Watertight ray triangle test (Woop, Benthin and Wald 2013)
+    // returns the distance and the barycentric weights of p0, p1 and p2
+    pub fn intersect_triangle(ray_origin: Vec3, ray_dir: Vec3, p0: Vec3, p1: Vec3, p2: Vec3) -> Option<(f32, Vec3)> {
+        // permute axes so the largest ray component becomes z
+        let kz = ray_dir.abs().max_position();
+        let mut kx = (kz + 1) % 3;
+        let mut ky = (kx + 1) % 3;
+        if ray_dir[kz] < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+
+        // shear constants
+        let sx = ray_dir[kx] / ray_dir[kz];
+        let sy = ray_dir[ky] / ray_dir[kz];
+        let sz = 1.0 / ray_dir[kz];
+
+        let a = p0 - ray_origin;
+        let b = p1 - ray_origin;
+        let c = p2 - ray_origin;
+
+        let ax = a[kx] - sx * a[kz];
+        let ay = a[ky] - sy * a[kz];
+        let bx = b[kx] - sx * b[kz];
+        let by = b[ky] - sy * b[kz];
+        let cx = c[kx] - sx * c[kz];
+        let cy = c[ky] - sy * c[kz];
+
+        let mut u = cx * by - cy * bx;
+        let mut v = ax * cy - ay * cx;
+        let mut w = bx * ay - by * ax;
+
+        // fall back to double precision on edges so neighbouring triangles never leak rays
+        if u == 0.0 || v == 0.0 || w == 0.0 {
+            u = (cx as f64 * by as f64 - cy as f64 * bx as f64) as f32;
+            v = (ax as f64 * cy as f64 - ay as f64 * cx as f64) as f32;
+            w = (bx as f64 * ay as f64 - by as f64 * ax as f64) as f32;
+        }
+
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+            return None;
+        }
+
+        let det = u + v + w;
+        if det == 0.0 {
+            return None;
+        }
+
+        let az = sz * a[kz];
+        let bz = sz * b[kz];
+        let cz = sz * c[kz];
+        let t = (u * az + v * bz + w * cz) / det;
+
+        if t < 0.001 {       // same epsilon as the other primitives
+            return None;
+        }
+
+        Some((t, Vec3::new(u, v, w) / det))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn single_triangle() {
+            let material = Material::default();
+            let triangle = Triangle {
+                v0: Vec3::new(0.0, 0.0, -1.0),
+                v1: Vec3::new(2.0, 0.0, -1.0),
+                v2: Vec3::new(0.0, 2.0, -1.0),
+                material: &material,
+            };
+
+            let hit = triangle.intersection(Vec3::new(0.5, 0.5, 1.0), Vec3::NEG_Z).expect("the ray points at the triangle");
+            assert!((hit.t - 2.0).abs() < 1e-6);
+            assert!((hit.position - Vec3::new(0.5, 0.5, -1.0)).length() < 1e-6);
+            assert!((hit.uv - Vec2::new(0.25, 0.25)).length() < 1e-6);
+            assert!((hit.geometric_normal - Vec3::Z).length() < 1e-6 && hit.front_face);
+
+            // from behind, beside it and pointing away
+            assert!(triangle.intersection(Vec3::new(0.5, 0.5, -3.0), Vec3::Z).is_some());
+            assert!(triangle.intersection(Vec3::new(1.5, 1.5, 1.0), Vec3::NEG_Z).is_none());
+            assert!(triangle.intersection(Vec3::new(0.5, 0.5, 1.0), Vec3::Z).is_none());
+
+            let bounds = triangle.bounds().unwrap();
+            assert_eq!((bounds.min, bounds.max), (Vec3::new(0.0, 0.0, -1.0), Vec3::new(2.0, 2.0, -1.0)));
+            assert!((triangle.sample_pdf(Vec3::ZERO, Vec3::ZERO) - 0.5).abs() < 1e-6);
+        }
+
+        fn assert_frame(hit: &HitRecord) {
+            let n = hit.shading_normal;
+            assert!((hit.tangent.length() - 1.0).abs() < 1e-5 && (hit.bitangent.length() - 1.0).abs() < 1e-5);
+            assert!(hit.tangent.dot(n).abs() < 1e-5 && hit.bitangent.dot(n).abs() < 1e-5 && hit.tangent.dot(hit.bitangent).abs() < 1e-5);
+        }
+
+        #[test]
+        fn sphere_hit_from_outside_and_inside() {
+            let material = Material::default();
+            let sphere = Sphere { center: Vec3::new(0.0, 0.0, -5.0), radius: 1.0, material: &material };
+
+            let outside = sphere.intersection(Vec3::ZERO, Vec3::NEG_Z).unwrap();
+            assert!((outside.t - 4.0).abs() < 1e-5 && outside.front_face);
+            assert!((outside.geometric_normal - Vec3::Z).length() < 1e-5);
+            // the equator at +z is a quarter turn around from the -x seam
+            assert!((outside.uv - Vec2::new(0.75, 0.5)).length() < 1e-5, "{}", outside.uv);
+            assert_frame(&outside);
+
+            // from the center the normals point back inwards at the ray
+            let inside = sphere.intersection(Vec3::new(0.0, 0.0, -5.0), Vec3::X).unwrap();
+            assert!(!inside.front_face);
+            assert!((inside.geometric_normal - Vec3::NEG_X).length() < 1e-5);
+            assert_eq!(inside.geometric_normal, inside.shading_normal);
+            assert_frame(&inside);
+        }
+
+        #[test]
+        fn mesh_hit_interpolates_vertex_data() {
+            let material = Material::default();
+            let mut mesh = TriangleMesh::new(
+                vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)],
+                vec![[0, 1, 2], [0, 2, 3]],
+                Some(vec![Vec3::Z, Vec3::Z, Vec3::new(0.0, 0.6, 0.8), Vec3::Z]),
+                Some(vec![Vec2::new(0.0, 0.0), Vec2::new(0.0, 1.0), Vec2::new(1.0, 1.0), Vec2::new(1.0, 0.0)]),
+                &material,
+            );
+            mesh.colors = Some(vec![Vec3::ZERO, Vec3::ZERO, Vec3::splat(255.0), Vec3::ZERO]);
+
+            // hits the centroid of the second triangle
+            let hit = mesh.intersection(Vec3::new(1.0 / 3.0, 2.0 / 3.0, 1.0), Vec3::NEG_Z).unwrap();
+            assert_eq!(hit.primitive_id, 1);
+            assert!((hit.uv - Vec2::new(2.0 / 3.0, 1.0 / 3.0)).length() < 1e-5, "{}", hit.uv);
+            assert!((hit.vertex_color.unwrap() - Vec3::splat(85.0)).length() < 1e-3);
+            assert!((hit.geometric_normal - Vec3::Z).length() < 1e-5);
+            assert!(hit.shading_normal.y > 0.0 && (hit.shading_normal.length() - 1.0).abs() < 1e-5);
+
+            // u runs along +y in this uv layout, the tangent follows it
+            assert!(hit.tangent.y > 0.9, "{}", hit.tangent);
+            assert_frame(&hit);
+        }
+
+        #[test]
+        fn shared_edge_is_watertight() {
+            // two triangles splitting a quad along its diagonal
+            let material = Material::default();
+            let corners = [Vec3::new(-1.0, -1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), Vec3::new(1.0, 1.0, 0.0), Vec3::new(-1.0, 1.0, 0.0)];
+            let lower = Triangle { v0: corners[0], v1: corners[1], v2: corners[2], material: &material };
+            let upper = Triangle { v0: corners[0], v1: corners[2], v2: corners[3], material: &material };
+
+            for i in 0..=100 {
+                let x = -1.0 + i as f32 * 0.02;
+                let origin = Vec3::new(x * 0.3, x * 0.7, 2.0);
+                let direction = Vec3::new(x, x, 0.0) - origin;
+                assert!(lower.intersection(origin, direction).is_some() || upper.intersection(origin, direction).is_some(), "leak at {x}");
+            }
         }
     }
 }
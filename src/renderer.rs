@@ -2,13 +2,139 @@ use core::f32;
 
 use crate::primitives::primitives::*;
 use crate::lights::*;
+use crate::bvh::{Aabb, Bvh};
+use crate::bsdf;
+use crate::environment::{pick, Environment};
+use crate::aov::AovBuffers;
+use crate::framebuffer::Framebuffer;
 use rand::prelude::*;
 
 use glam::Vec3;
 
+// bounces before Russian roulette may end a path
+const ROULETTE_DEPTH: u32 = 3;
+
 pub struct Scene<'a> {
     pub objects: Vec<&'a dyn Primitives>,
     pub lights: Vec<&'a lights::Light>,
+    // lights everything rays escape to, Camera::background_color is used without one
+    pub environment: Option<Environment>,
+    // built over the bounded objects, planes and friends sit in unbounded
+    bvh: Bvh,
+    bounded: Vec<usize>,
+    unbounded: Vec<usize>,
+    // emissive objects that can be sampled as area lights
+    emitters: Vec<usize>,
+    // bounces a path may take after the first hit
+    pub max_depth: u32,
+    // per object, objects that share a Material get the same id
+    material_ids: Vec<u32>,
+}
+
+impl<'a> Scene<'a> {
+    pub fn new(objects: Vec<&'a dyn Primitives>, lights: Vec<&'a lights::Light>) -> Self {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        let mut bounds: Vec<Aabb> = Vec::new();
+
+        for (index, obj) in objects.iter().enumerate() {
+            match obj.bounds() {
+                Some(b) => {
+                    bounded.push(index);
+                    bounds.push(b);
+                }
+                None => unbounded.push(index),
+            }
+        }
+
+        // unbounded objects have no finite area to sample, their emission is only picked up by bounces
+        let emitters = bounded
+            .iter()
+            .copied()
+            .filter(|&index| objects[index].get_material().emission.max_element() > 0.0)
+            .collect();
+
+        let mut materials: Vec<&Material> = Vec::new();
+        let material_ids = objects
+            .iter()
+            .map(|obj| {
+                let material = obj.get_material();
+                let id = materials.iter().position(|&known| std::ptr::eq(known, material)).unwrap_or_else(|| {
+                    materials.push(material);
+                    materials.len() - 1
+                });
+                id as u32
+            })
+            .collect();
+
+        Scene {
+            objects,
+            lights,
+            environment: None,
+            bvh: Bvh::build(&bounds),
+            bounded,
+            unbounded,
+            emitters,
+            max_depth: 6,
+            material_ids,
+        }
+    }
+
+    // Radiance emitted by the hit surface, emissive surfaces glow on both sides
+    pub fn emitted(&self, hit: &HitRecord) -> Vec3 {
+        self.objects[hit.instance_id as usize].get_material().emission
+    }
+
+    // Solid angle density of sample_emitters picking the point a ray from ray_origin hit,
+    // zero for objects that are not sampled as lights
+    fn emitter_pdf(&self, ray_origin: Vec3, hit: &HitRecord) -> f32 {
+        let index = hit.instance_id as usize;
+        if !self.emitters.contains(&index) {
+            return 0.0;
+        }
+
+        let to_hit = hit.position - ray_origin;
+        let distance_squared = to_hit.length_squared();
+        let cos_light = hit.geometric_normal.dot(to_hit).abs() / distance_squared.sqrt();
+        if cos_light <= 1e-6 {
+            return 0.0;
+        }
+
+        self.objects[index].sample_pdf(ray_origin, hit.position) * distance_squared / cos_light / self.emitters.len() as f32
+    }
+
+    // Closest hit along the ray, with instance_id set to the object index
+    pub fn closest_hit(&self, ray_origin: Vec3, ray_direction: Vec3) -> Option<HitRecord> {
+        let mut closest: Option<HitRecord> = None;
+
+        let mut test = |index: usize| {
+            let mut hit = self.objects[index].intersection(ray_origin, ray_direction)?;
+            if hit.t > 0.001 && closest.is_none_or(|c| hit.t < c.t) {
+                hit.instance_id = index as u32;
+                closest = Some(hit);
+                return Some(hit.t);
+            }
+            None
+        };
+
+        let mut t_max = f32::INFINITY;
+        for &index in self.unbounded.iter() {
+            if let Some(t) = test(index) {
+                t_max = t;
+            }
+        }
+        self.bvh.traverse(ray_origin, ray_direction, t_max, |item| test(self.bounded[item]));
+
+        closest
+    }
+
+    // Shadow ray test, true if anything blocks the segment up to max_distance
+    pub fn occluded(&self, ray_origin: Vec3, ray_direction: Vec3, max_distance: f32) -> bool {
+        let blocks = |index: usize| self.objects[index].occluded(ray_origin, ray_direction, max_distance);
+
+        self.unbounded.iter().any(|&index| blocks(index))
+            || self.bvh.any_hit(ray_origin, ray_direction, max_distance, |item| blocks(self.bounded[item]))
+    }
 }
 
 pub struct Camera {
@@ -17,6 +143,257 @@ pub struct Camera {
     pub up: Vec3,
     pub fov: f32,
     pub background_color: Vec3,
+    // thin lens, an aperture_radius of 0.0 gives a pinhole camera
+    pub aperture_radius: f32,
+    pub focus_distance: f32,
+    pub aperture_shape: ApertureShape,
+    pub projection: Projection,
+}
+
+pub enum Projection {
+    Perspective,
+    // height of the view volume in world units
+    Orthographic { height: f32 },
+    // equidistant fisheye, fov covers the whole image circle
+    Fisheye,
+    // 360 x 180 degree panorama
+    Equirectangular,
+    // left eye in the left half, right eye in the right half
+    Stereo { eye_separation: f32, panoramic: bool },
+}
+
+pub enum ApertureShape {
+    Circle,
+    // regular polygon with the given number of blades, rotation in degrees
+    Polygon { blades: u32, rotation: f32 },
+    Mask(ApertureMask),
+}
+
+// Grayscale transmission image covering the square [-1, 1] x [-1, 1] of the lens
+pub struct ApertureMask {
+    pub width: usize,
+    pub height: usize,
+    // cumulative distribution for picking a texel by its transmission
+    cdf: Vec<f32>,
+}
+
+impl ApertureMask {
+    // Transmission in 0..1 with rows from top to bottom, None when nothing passes through the mask
+    pub fn new(width: usize, height: usize, data: &[f32]) -> Option<Self> {
+        if width == 0 || height == 0 || data.len() != width * height {
+            return None;
+        }
+
+        let mut cdf = Vec::with_capacity(data.len() + 1);
+        let mut total = 0.0;
+        cdf.push(0.0);
+        for value in data.iter() {
+            total += value.clamp(0.0, 1.0);
+            cdf.push(total);
+        }
+        if total <= 0.0 {
+            return None;
+        }
+        for value in cdf.iter_mut() {
+            *value /= total;
+        }
+
+        Some(ApertureMask { width, height, cdf })
+    }
+}
+
+impl ApertureShape {
+    // Point on the unit aperture, distributed according to the shape
+    fn sample(&self, rng: &mut ThreadRng) -> (f32, f32) {
+        match self {
+            ApertureShape::Circle => {
+                let r = rng.random::<f32>().sqrt();
+                let theta = 2.0 * std::f32::consts::PI * rng.random::<f32>();
+                (r * theta.cos(), r * theta.sin())
+            }
+            ApertureShape::Polygon { blades, rotation } => {
+                let blades = (*blades).max(3);
+                let step = 2.0 * std::f32::consts::PI / blades as f32;
+
+                // every wedge has the same area, so pick one uniformly and sample the triangle
+                let wedge = rng.random_range(0..blades) as f32;
+                let a0 = rotation.to_radians() + wedge * step;
+                let a1 = a0 + step;
+
+                let mut s: f32 = rng.random();
+                let mut t: f32 = rng.random();
+                if s + t > 1.0 {
+                    s = 1.0 - s;
+                    t = 1.0 - t;
+                }
+                (s * a0.cos() + t * a1.cos(), s * a0.sin() + t * a1.sin())
+            }
+            ApertureShape::Mask(mask) => {
+                // texel by transmission, then a uniform point inside it
+                let texel = pick(&mask.cdf, rng.random());
+                let px = (texel % mask.width) as f32 + rng.random::<f32>();
+                let py = (texel / mask.width) as f32 + rng.random::<f32>();
+                (px / mask.width as f32 * 2.0 - 1.0, 1.0 - py / mask.height as f32 * 2.0)
+            }
+        }
+    }
+}
+
+impl Camera {
+    // Orthonormal camera frame as (right, up, forward), built from look_at and up
+    pub fn basis(&self) -> (Vec3, Vec3, Vec3) {
+        let forward = (self.look_at - self.position).normalize();
+
+        // fall back to another up vector if looking straight along it
+        let up_hint = if forward.cross(self.up).length_squared() < 1e-8 {
+            if forward.y.abs() < 0.9 { Vec3::Y } else { Vec3::Z }
+        } else {
+            self.up
+        };
+
+        let right = forward.cross(up_hint).normalize();
+        let up = right.cross(forward);
+        (right, up, forward)
+    }
+
+    // Primary ray through a random point inside pixel (x, y), None where the projection covers no direction
+    pub fn generate_ray(&self, x: usize, y: usize, width: i32, height: i32, rng: &mut ThreadRng) -> Option<(Vec3, Vec3)> {
+        // jitter inside the pixel footprint for anti aliasing
+        let mut px = x as f32 + rng.random::<f32>();
+        let py = y as f32 + rng.random::<f32>();
+        let mut image_width = width as f32;
+        let image_height = height as f32;
+
+        // side by side stereo renders each eye into its own half of the image
+        let mut eye = 0.0;
+        if let Projection::Stereo { .. } = self.projection {
+            image_width *= 0.5;
+            if px < image_width {
+                eye = -0.5;
+            } else {
+                eye = 0.5;
+                px -= image_width;
+            }
+        }
+
+        let u = (px / image_width) * 2.0 - 1.0;
+        let v = 1.0 - (py / image_height) * 2.0;
+        let aspect_ratio = image_width / image_height;
+
+        let (right, up, forward) = self.basis();
+
+        match self.projection {
+            Projection::Perspective => {
+                let direction = self.perspective_direction(u, v, aspect_ratio);
+                Some(self.apply_lens(self.position, direction, rng))
+            }
+            Projection::Orthographic { height } => {
+                let origin = self.position + right * (u * height * 0.5 * aspect_ratio) + up * (v * height * 0.5);
+                Some(self.apply_lens(origin, forward, rng))
+            }
+            Projection::Fisheye => {
+                // equidistant mapping, fov is the angle across the image circle
+                let u = u * aspect_ratio;
+                let r = (u * u + v * v).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let theta = r * self.fov.to_radians() * 0.5;
+                let phi = v.atan2(u);
+                let direction = forward * theta.cos() + (right * phi.cos() + up * phi.sin()) * theta.sin();
+                Some((self.position, direction.normalize()))
+            }
+            Projection::Equirectangular => {
+                Some((self.position, self.equirectangular_direction(u, v)))
+            }
+            Projection::Stereo { eye_separation, panoramic } => {
+                if panoramic {
+                    // omni directional stereo, the eyes sit on a circle around the up axis
+                    let direction = self.equirectangular_direction(u, v);
+                    let side = direction.cross(up).normalize_or_zero();
+                    Some((self.position + side * (eye * eye_separation), direction))
+                } else {
+                    // off axis eyes converging on the focus plane
+                    let direction = self.perspective_direction(u, v, aspect_ratio);
+                    let focus_point = self.position + direction * (self.focus_distance / direction.dot(forward));
+                    let origin = self.position + right * (eye * eye_separation);
+                    Some((origin, (focus_point - origin).normalize()))
+                }
+            }
+        }
+    }
+
+    fn perspective_direction(&self, u: f32, v: f32, aspect_ratio: f32) -> Vec3 {
+        let (right, up, forward) = self.basis();
+
+        // fov is the vertical field of view in degrees, the horizontal extent follows the aspect ratio
+        let half_height = (self.fov.to_radians() * 0.5).tan();
+        let half_width = half_height * aspect_ratio;
+
+        (forward + right * (u * half_width) + up * (v * half_height)).normalize()
+    }
+
+    // full sphere, u spans the longitude and v the latitude around the camera frame
+    fn equirectangular_direction(&self, u: f32, v: f32) -> Vec3 {
+        let (right, up, forward) = self.basis();
+
+        let longitude = u * std::f32::consts::PI;
+        let latitude = v * std::f32::consts::FRAC_PI_2;
+
+        let horizontal = forward * longitude.cos() + right * longitude.sin();
+        (horizontal * latitude.cos() + up * latitude.sin()).normalize()
+    }
+
+    fn apply_lens(&self, origin: Vec3, direction: Vec3, rng: &mut ThreadRng) -> (Vec3, Vec3) {
+        if self.aperture_radius <= 0.0 {
+            return (origin, direction);
+        }
+
+        let (right, up, forward) = self.basis();
+
+        // everything on the focus plane stays sharp, move the origin across the lens
+        let focus_point = origin + direction * (self.focus_distance / direction.dot(forward));
+        let (lx, ly) = self.aperture_shape.sample(rng);
+        let lens_origin = origin + (right * lx + up * ly) * self.aperture_radius;
+
+        (lens_origin, (focus_point - lens_origin).normalize())
+    }
+}
+
+// Everything one camera path found, color is the full estimate and the four light passes
+// hold the part of it that was scattered by the first surface
+pub struct PathSample {
+    pub color: Vec3,
+    pub direct_diffuse: Vec3,
+    pub direct_specular: Vec3,
+    pub indirect_diffuse: Vec3,
+    pub indirect_specular: Vec3,
+    // first surface the camera ray hit, None when it escaped
+    pub surface: Option<SurfaceInfo>,
+}
+
+pub struct SurfaceInfo {
+    // surface color in 0..1
+    pub albedo: Vec3,
+    pub normal: Vec3,
+    pub position: Vec3,
+    // distance from the camera along the ray
+    pub depth: f32,
+    // index into Scene::objects
+    pub object_id: u32,
+    // objects sharing a Material share the id
+    pub material_id: u32,
+}
+
+impl PathSample {
+    const EMPTY: PathSample = PathSample {
+        color: Vec3::ZERO,
+        direct_diffuse: Vec3::ZERO,
+        direct_specular: Vec3::ZERO,
+        indirect_diffuse: Vec3::ZERO,
+        indirect_specular: Vec3::ZERO,
+        surface: None,
+    };
 }
 
 pub fn render_function(
@@ -26,244 +403,567 @@ pub fn render_function(
     height: i32,
     camera: &Camera,
     scene: &Scene
-) -> Vec3 {
-    let u = (x as f32 / width as f32) * 2.0 - 1.0;
-    let v = 1.0 - (y as f32 / height as f32) * 2.0;
-
+) -> PathSample {
     let rng = &mut rand::rng(); 
 
-    let ray_origin = camera.position;
-    let ray_direction = Vec3::new(u + rng.random::<f32>() * 0.003, v + rng.random::<f32>() * 0.003, -1.0).normalize();
-
-    let mut closest_t = f32::INFINITY;
-    let mut hit_normal = Vec3::ZERO;
-    let mut hit_point = Vec3::ZERO;
-    let mut hit_anything = false;
+    let Some((ray_origin, ray_direction)) = camera.generate_ray(x, y, width, height, rng) else {
+        return PathSample::EMPTY;
+    };
 
-    let mut direct_hit_material: &Material = &Material::default();  
-
-    let ray_bounce_depth= 6;
+    trace_path(ray_origin, ray_direction, camera, scene, rng)
+}
 
-    // Find closest intersection
-    for obj in scene.objects.iter() {
-        if let Some((t, normal)) = obj.intersection(ray_origin, ray_direction) {
-            if t < closest_t && t > 0.001 {
-                closest_t = t;
-                hit_normal = normal;
-                hit_point = ray_origin + ray_direction * t;
-                hit_anything = true;
-                direct_hit_material = &obj.get_material()
+// Averages samples paths per pixel into a float framebuffer, with the AOV layers when asked for
+pub fn render_image(camera: &Camera, scene: &Scene, width: usize, height: usize, samples: u32, aovs: bool) -> Framebuffer {
+    let mut image = Framebuffer::new(width, height);
+    let mut passes = aovs.then(|| AovBuffers::new(width, height));
+
+    for y in 0..height {
+        for x in 0..width {
+            let index = y * width + x;
+            let mut total_pixel_color = Vec3::ZERO;
+            for _ in 0..samples {
+                let sample = render_function(x, y, width as i32, height as i32, camera, scene);
+                total_pixel_color += sample.color;
+                if let Some(passes) = passes.as_mut() {
+                    passes.add(index, &sample);
+                }
             }
+            image.color[index] = total_pixel_color / samples.max(1) as f32;
         }
     }
 
-    if !hit_anything {
-        return camera.background_color;
+    if let Some(passes) = passes {
+        image.layers = passes.into_layers(samples);
     }
+    image
+}
 
-    let perfect_reflect_direction = ray_direction.reflect(hit_normal);
+// Follows one path from the camera, throughput is the product of all bsdf weights
+// and absorption so far, i.e. how much of the light found further along reaches the camera
+fn trace_path(
+    mut ray_origin: Vec3,
+    mut ray_direction: Vec3,
+    camera: &Camera,
+    scene: &Scene,
+    rng: &mut ThreadRng
+) -> PathSample {
+    let mut path = PathSample::EMPTY;
+    let mut throughput = Vec3::ONE;
+    // share of the throughput that left the first surface through its diffuse lobes
+    let mut diffuse_throughput = Vec3::ZERO;
+    // pdf of the bounce that produced the ray, None for camera rays and specular bounces
+    // so that lights found along them count at full weight
+    let mut bsdf_pdf: Option<f32> = None;
+
+    for depth in 0..=scene.max_depth {
+        // find first ray collision and the lights the ray runs into on the way
+        let hit = scene.closest_hit(ray_origin, ray_direction);
+        let found_light = light_along_ray(scene, camera, ray_origin, ray_direction, hit.as_ref(), bsdf_pdf);
+        let Some(hit) = hit else {
+            path.add_light(depth, throughput, diffuse_throughput, found_light, Vec3::ZERO);
+            break;
+        };
+        let material = scene.objects[hit.instance_id as usize].get_material();
+        let albedo = material.base_color(&hit);
+
+        // Convert object color to 0..1 range if it's 0..255
+        let object_color = albedo / 255.0;
+
+        if depth == 0 {
+            path.surface = Some(SurfaceInfo {
+                albedo: object_color,
+                normal: hit.shading_normal,
+                position: hit.position,
+                depth: hit.t,
+                object_id: hit.instance_id,
+                material_id: scene.material_ids[hit.instance_id as usize],
+            });
+        }
 
-    // Accumulate all lights
-    let mut accumulated_light = Vec3::ZERO;
+        // light that travelled inside a glass object gets absorbed on the way
+        let transmittance = bsdf::medium_transmittance(material, &hit);
+        throughput *= transmittance;
+        diffuse_throughput *= transmittance;
+
+        let direct_light = sample_direct_light(scene, &hit, material, object_color, ray_direction, rng);
+        if depth == 0 {
+            // the first surface splits its direct light by lobe itself
+            path.color += throughput * (found_light + direct_light.diffuse + direct_light.specular);
+            path.direct_diffuse += throughput * direct_light.diffuse;
+            path.direct_specular += throughput * direct_light.specular;
+        } else {
+            path.add_light(depth, throughput, diffuse_throughput, found_light, direct_light.diffuse + direct_light.specular);
+        }
 
-    for light in scene.lights.iter() {
-        match light {
-            lights::Light::Point(pl) => {
-                let to_light = pl.position - hit_point;
-                let distance = to_light.length();
-                let light_dir = to_light / distance;
+        if depth == scene.max_depth {
+            break;
+        }
 
-                // Shadow ray
-                let shadow_origin = hit_point + hit_normal * 0.001;
-                let mut in_shadow = false;
+        let Some(bounce) = bsdf::sample(material, &hit, object_color, ray_direction, rng) else {
+            break;
+        };
+        if depth == 0 {
+            diffuse_throughput = throughput * bounce.diffuse;
+        } else {
+            diffuse_throughput *= bounce.weight;
+        }
+        throughput *= bounce.weight;
+
+        // Russian roulette, paths that carry little light are likely to stop
+        // and the survivors are scaled up by the same amount to keep the average
+        if depth >= ROULETTE_DEPTH {
+            let survival = throughput.max_element().min(0.95);
+            if rng.random::<f32>() >= survival {
+                break;
+            }
+            throughput /= survival;
+            diffuse_throughput /= survival;
+        }
 
-                for obj in scene.objects.iter() {
-                    if let Some((t_shadow, _)) = obj.intersection(shadow_origin, light_dir) {
-                        if t_shadow > 0.0001 && t_shadow < distance {
-                            in_shadow = true;
-                            break;
-                        }
-                    }
-                }
+        ray_origin = bsdf::offset_origin(&hit, bounce.direction);
+        ray_direction = bounce.direction;
+        bsdf_pdf = (!bounce.specular).then_some(bounce.pdf);
+    }
 
-                if in_shadow {continue}
+    path
+}
 
-                // Lambert diffuse
-                let ndotl = hit_normal.dot(light_dir).max(0.0);
-                let attenuation = pl.intensity / (distance * distance);
-                accumulated_light += (direct_hit_material.color / std::f32::consts::PI) * (pl.color / 255.0 * attenuation) * ndotl // remove for weird cell shade
-            }
+impl PathSample {
+    // Adds light found at a vertex, found_light arrived along the ray into it and
+    // direct_light was gathered there by light sampling
+    fn add_light(&mut self, depth: u32, throughput: Vec3, diffuse_throughput: Vec3, found_light: Vec3, direct_light: Vec3) {
+        self.color += throughput * (found_light + direct_light);
+        // what the camera sees directly belongs to no pass
+        if depth == 0 {
+            return;
         }
+
+        let specular_throughput = throughput - diffuse_throughput;
+        // light found by the first bounce is the bsdf sampled half of the first surface's direct light
+        let (direct, indirect) = if depth == 1 { (found_light, direct_light) } else { (Vec3::ZERO, found_light + direct_light) };
+        self.direct_diffuse += diffuse_throughput * direct;
+        self.direct_specular += specular_throughput * direct;
+        self.indirect_diffuse += diffuse_throughput * indirect;
+        self.indirect_specular += specular_throughput * indirect;
     }
+}
 
-    let random_hemisphere_vector= sample_cosine_hemisphere(hit_normal, rng)
-        .lerp(perfect_reflect_direction, 1.0 - direct_hit_material.roughness)
-        .normalize();
-    // let random_hemisphere_vector = ray_direction.reflect(hit_normal);
+// Reflected light split by the lobes that scattered it
+#[derive(Clone, Copy, Default)]
+struct DirectLight {
+    diffuse: Vec3,
+    specular: Vec3,
+}
 
-    let indirect_color = cast_ray(
-        hit_point,
-        random_hemisphere_vector,
-        camera,
-        scene,
-        rng,
-        0,
-        ray_bounce_depth
-    );
+impl DirectLight {
+    // incoming radiance, already divided by its pdf and weighted, scattered by the bsdf
+    fn scattered(eval: &bsdf::BsdfEval, radiance: Vec3) -> Self {
+        DirectLight { diffuse: eval.diffuse * radiance, specular: eval.specular * radiance }
+    }
+}
+
+impl std::ops::AddAssign for DirectLight {
+    fn add_assign(&mut self, other: DirectLight) {
+        self.diffuse += other.diffuse;
+        self.specular += other.specular;
+    }
+}
 
-    // Convert object color to 0..1 range if it's 0..255
-    let object_color = direct_hit_material.color / 255.0;
+// Light sampling half of the direct lighting, each strategy is weighted against the bsdf
+// sampling the same direction, light_along_ray adds the other half at the next vertex
+fn sample_direct_light(
+    scene: &Scene,
+    hit: &HitRecord,
+    material: &Material,
+    albedo: Vec3,
+    ray_direction: Vec3,
+    rng: &mut ThreadRng
+) -> DirectLight {
+    let mut light = DirectLight::default();
+
+    // delta materials only see lights through their bounce
+    if material.kind.is_specular() {
+        return light;
+    }
 
-    // Multiply accumulated light by object color
-    let mut shaded_color = object_color;
+    let shadow_origin = hit.position + hit.geometric_normal * 0.001;
 
-    shaded_color = shaded_color * (indirect_color + accumulated_light); // this fixed everything
+    for source in scene.lights.iter() {
+        let Some(sample) = source.sample(hit.position, rng) else { continue };
+        if scene.occluded(shadow_origin, sample.direction, sample.distance) { continue }
 
-    // Clamp final color to [0,1] per channel
-    // shaded_color = shaded_color.min(Vec3::splat(1.0));
+        let eval = bsdf::eval(material, hit, albedo, ray_direction, sample.direction);
+        light += DirectLight::scattered(&eval, sample.radiance * bsdf::power_heuristic(sample.pdf, eval.pdf));
+    }
 
-    // pack_color(shaded_color)
-    shaded_color
+    light += sample_emitters(scene, hit, material, albedo, ray_direction, rng);
+    light += sample_environment(scene, hit, material, albedo, ray_direction, rng);
+    light
 }
 
-fn cast_ray (
-    ray_origin: Vec3, 
-    ray_direction: Vec3, 
-    camera: &Camera, 
-    scene: &Scene, 
-    random: &mut ThreadRng, 
-    recursion_depth: i32,
-    max_depth: i32
+// Bsdf sampling half of the direct lighting, light the ray finds before or at hit
+// weighted against the light sampling done where the ray started
+// bsdf_pdf is None for camera rays and specular bounces, those count everything at full weight
+fn light_along_ray(
+    scene: &Scene,
+    camera: &Camera,
+    ray_origin: Vec3,
+    ray_direction: Vec3,
+    hit: Option<&HitRecord>,
+    bsdf_pdf: Option<f32>
 ) -> Vec3 {
-    if recursion_depth >= max_depth {
-        return Vec3::ZERO
+    let weight = |light_pdf: f32| bsdf_pdf.map_or(1.0, |pdf| bsdf::power_heuristic(pdf, light_pdf));
+    let mut radiance = Vec3::ZERO;
+
+    // light surfaces in front of the hit, directional lights only when the ray escapes
+    for source in scene.lights.iter() {
+        if let Some(light_hit) = source.hit(ray_origin, ray_direction) {
+            if hit.is_none_or(|hit| light_hit.distance < hit.t) {
+                radiance += light_hit.radiance * weight(light_hit.pdf);
+            }
+        }
     }
 
-    if random.random_range(0.0..1.0) > 0.90 {
-        return Vec3::ZERO;
+    match hit {
+        Some(hit) => {
+            let emission = scene.emitted(hit);
+            if emission.max_element() > 0.0 {
+                radiance += emission * weight(scene.emitter_pdf(ray_origin, hit));
+            }
+        }
+        None => {
+            radiance += match &scene.environment {
+                Some(env) => env.radiance(ray_direction) * weight(env.pdf(ray_direction)),
+                None => camera.background_color,
+            };
+        }
     }
 
-    let mut closest_distance: f32 = f32::INFINITY; 
-    let mut hit_normal: Vec3 = Vec3::ZERO;
-    let mut hit_point: Vec3 = Vec3::ZERO;
-    let mut hit_anything: bool = false;
-    let mut light_dot: f32 = 0.0;
+    radiance
+}
 
-    let mut direct_hit_material: &Material = &Material::default();  
+// Next event estimation towards one randomly picked emissive object
+fn sample_emitters(
+    scene: &Scene,
+    hit: &HitRecord,
+    material: &Material,
+    albedo: Vec3,
+    ray_direction: Vec3,
+    rng: &mut ThreadRng
+) -> DirectLight {
+    if scene.emitters.is_empty() {
+        return DirectLight::default();
+    }
 
-    // find first ray collision
-    for obj in scene.objects.iter() {
-        if let Some((t, normal)) = obj.intersection(ray_origin, ray_direction) {
-            if t < closest_distance && t > 0.001 {
-                closest_distance = t;
-                hit_normal = normal;
-                hit_point = ray_origin + ray_direction * t;
-                hit_anything = true;
-                direct_hit_material = obj.get_material();
-            }
-        }
+    let index = scene.emitters[rng.random_range(0..scene.emitters.len())];
+    let emitter = scene.objects[index];
+    let Some(sample) = emitter.sample_point(hit.position, rng) else {
+        return DirectLight::default();
+    };
+
+    // aim from the offset origin, otherwise the shadow ray passes beside the sample and can clip the emitter
+    let shadow_origin = hit.position + hit.geometric_normal * 0.001;
+    let to_light = sample.position - shadow_origin;
+    let distance = to_light.length();
+    if distance <= 1e-4 {
+        return DirectLight::default();
     }
+    let light_dir = to_light / distance;
 
-    // missed collision check
-    if !hit_anything {
-        return camera.background_color;
+    let cos_light = sample.normal.dot(-light_dir).abs();
+    if cos_light <= 1e-6 {
+        return DirectLight::default();
     }
 
-    let perfect_reflect_direction = ray_direction.reflect(hit_normal);
+    // stop the shadow ray just short of the emitter so it does not block itself
+    if scene.occluded(shadow_origin, light_dir, distance * (1.0 - 1e-3)) {
+        return DirectLight::default();
+    }
 
-    // calculate direct to collision point
-    let mut direct_light = Vec3::ZERO;
-    for light in scene.lights.iter() {
-        match light {
-            lights::Light::Point(point_light) => {
-                let to_light = point_light.position - hit_point;
-                let distance = to_light.length();
-                let light_dir = to_light / distance;
-                let shadow_origin = hit_point + hit_normal * 0.001;
-                let mut in_shadow = false;
+    let eval = bsdf::eval(material, hit, albedo, ray_direction, light_dir);
 
-                for obj in scene.objects.iter() {
-                    if let Some((t_shadow, _)) = obj.intersection(shadow_origin, light_dir) {
-                        if t_shadow > 0.0001 && t_shadow < distance {
-                            in_shadow = true;
-                            break;
-                        }
-                    }
-                }
+    // convert the area pdf to solid angle and account for picking one of the emitters
+    let pdf = sample.pdf * distance * distance / cos_light / scene.emitters.len() as f32;
+    let radiance = emitter.get_material().emission / pdf;
+    DirectLight::scattered(&eval, radiance * bsdf::power_heuristic(pdf, eval.pdf))
+}
 
-                if in_shadow { continue; }
+// Next event estimation towards the environment map, importance sampled by its luminance
+fn sample_environment(
+    scene: &Scene,
+    hit: &HitRecord,
+    material: &Material,
+    albedo: Vec3,
+    ray_direction: Vec3,
+    rng: &mut ThreadRng
+) -> DirectLight {
+    let Some(sample) = scene.environment.as_ref().and_then(|env| env.sample(rng)) else {
+        return DirectLight::default();
+    };
+
+    let shadow_origin = hit.position + hit.geometric_normal * 0.001;
+    if scene.occluded(shadow_origin, sample.direction, f32::INFINITY) {
+        return DirectLight::default();
+    }
 
-                light_dot = hit_normal.dot(light_dir).max(0.0);
-                let attenuation = point_light.intensity / (distance * distance);
-                // direct_light += point_light.color * ndotl * attenuation;
-                direct_light += (direct_hit_material.color / std::f32::consts::PI) * (point_light.color / 255.0 * attenuation) * light_dot
-            }
+    let eval = bsdf::eval(material, hit, albedo, ray_direction, sample.direction);
+    DirectLight::scattered(&eval, sample.radiance / sample.pdf * bsdf::power_heuristic(sample.pdf, eval.pdf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::primitives::{Plane, Sphere};
+
+    pub(super) fn camera(position: Vec3, look_at: Vec3, up: Vec3, fov: f32) -> Camera {
+        Camera {
+            position,
+            look_at,
+            up,
+            fov,
+            background_color: Vec3::ZERO,
+            aperture_radius: 0.0,
+            focus_distance: 1.0,
+            aperture_shape: ApertureShape::Circle,
+            projection: Projection::Perspective,
+        }
+    }
+
+    fn average(image: &Framebuffer) -> Vec3 {
+        image.color.iter().sum::<Vec3>() / image.color.len() as f32
+    }
+
+    fn assert_orthonormal((right, up, forward): (Vec3, Vec3, Vec3)) {
+        for axis in [right, up, forward] {
+            assert!((axis.length() - 1.0).abs() < 1e-5, "{axis}");
         }
+        assert!(right.dot(up).abs() < 1e-5 && up.dot(forward).abs() < 1e-5 && forward.dot(right).abs() < 1e-5);
+        // right handed, right x up points back towards the viewer
+        assert!((right.cross(up) + forward).length() < 1e-5);
     }
 
-    // calculate indirect lighting recursivly n+1
-    let random_hemisphere_vector= sample_cosine_hemisphere(hit_normal, random)
-        .lerp(perfect_reflect_direction, 1.0 - direct_hit_material.roughness)
-        .normalize();
-    // let random_hemisphere_vector = ray_direction.reflect(hit_normal);
+    #[test]
+    fn basis_follows_look_at_and_up() {
+        let cam = camera(Vec3::new(1.0, 2.0, 3.0), Vec3::new(1.0, 2.0, -1.0), Vec3::Y, 60.0);
+        let (right, up, forward) = cam.basis();
+        assert_orthonormal((right, up, forward));
+        assert!((forward - Vec3::NEG_Z).length() < 1e-6);
+        assert!((up - Vec3::Y).length() < 1e-6);
+        assert!((right - Vec3::X).length() < 1e-6);
+
+        // a tilted up hint is straightened against the view direction
+        let cam = camera(Vec3::ZERO, Vec3::new(3.0, 1.0, 0.5), Vec3::new(0.2, 1.0, 0.3), 60.0);
+        let (_, up, _) = cam.basis();
+        assert_orthonormal(cam.basis());
+        assert!(up.dot(Vec3::new(0.2, 1.0, 0.3)) > 0.0);
+
+        // looking straight along up still gives a frame
+        let cam = camera(Vec3::ZERO, Vec3::new(0.0, -5.0, 0.0), Vec3::Y, 60.0);
+        assert_orthonormal(cam.basis());
+    }
 
-    let indirect_light_value = cast_ray (
-        hit_point + hit_normal * 0.001,
-        random_hemisphere_vector,
-        camera,
-        scene,
-        random,
-        recursion_depth + 1,
-        max_depth
-    );
+    #[test]
+    fn center_ray_hits_the_look_at_point() {
+        let look_at = Vec3::new(-2.0, 1.0, 4.0);
+        let cam = camera(Vec3::new(1.0, 0.5, -1.0), look_at, Vec3::Y, 45.0);
+        let direction = cam.perspective_direction(0.0, 0.0, 1.5);
+        assert!((direction - (look_at - cam.position).normalize()).length() < 1e-6);
+    }
 
-    let object_color = direct_hit_material.color / 255.0;
+    #[test]
+    fn aperture_samples_follow_the_mask() {
+        let rng = &mut rand::rng();
+        assert!(ApertureMask::new(2, 2, &[0.0; 4]).is_none());
+        assert!(ApertureMask::new(2, 2, &[1.0; 3]).is_none());
+
+        // the right texel passes three times as much light as the left one
+        let shape = ApertureShape::Mask(ApertureMask::new(2, 1, &[0.25, 0.75]).unwrap());
+        let n = 20_000;
+        let right = (0..n).filter(|_| shape.sample(rng).0 > 0.0).count();
+        assert!((right as f32 / n as f32 - 0.75).abs() < 0.02, "{right}");
+
+        // rows run from top to bottom, the only open texel is the top left one
+        let shape = ApertureShape::Mask(ApertureMask::new(2, 2, &[1.0, 0.0, 0.0, 0.0]).unwrap());
+        for _ in 0..1000 {
+            let (x, y) = shape.sample(rng);
+            assert!((-1.0..=0.0).contains(&x) && (0.0..=1.0).contains(&y), "{x} {y}");
+        }
+    }
 
-    let combined_color = object_color * (indirect_light_value + direct_light);
+    #[test]
+    fn aperture_shapes_stay_on_the_lens() {
+        let rng = &mut rand::rng();
+        for _ in 0..1000 {
+            let (x, y) = ApertureShape::Circle.sample(rng);
+            assert!(x * x + y * y <= 1.0 + 1e-6);
 
-    return combined_color;
-}
+            // a square turned by 45 degrees has its edges at |x| + |y| = 1
+            let (x, y) = ApertureShape::Polygon { blades: 4, rotation: 0.0 }.sample(rng);
+            assert!(x.abs() + y.abs() <= 1.0 + 1e-5, "{x} {y}");
+        }
+    }
+
+    #[test]
+    fn lens_rays_meet_on_the_focus_plane() {
+        let mut cam = camera(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y, 40.0);
+        cam.aperture_radius = 0.2;
+        cam.focus_distance = 3.0;
+        let rng = &mut rand::rng();
+
+        let direction = cam.perspective_direction(0.3, -0.2, 1.0);
+        let focus_point = direction * (3.0 / direction.dot(Vec3::NEG_Z));
+        let mut spread = 0.0f32;
+        for _ in 0..100 {
+            let (origin, lens_direction) = cam.apply_lens(Vec3::ZERO, direction, rng);
+            assert!(origin.z.abs() < 1e-6 && origin.length() <= 0.2 + 1e-5);
+            let t = (focus_point.z - origin.z) / lens_direction.z;
+            assert!((origin + lens_direction * t - focus_point).length() < 1e-4);
+            spread = spread.max(origin.length());
+        }
+        assert!(spread > 0.1);
+    }
 
-fn sample_cosine_hemisphere(n: Vec3, rng: &mut ThreadRng) -> Vec3 {
-    // sample disk with sqrt transform
-    let u1: f32 = rng.random();
-    let u2: f32 = rng.random();
+    #[test]
+    fn projections_at_the_image_corners() {
+        let rng = &mut rand::rng();
+        let mut cam = camera(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y, 90.0);
+        let angle = |a: Vec3, b: Vec3| a.angle_between(b).to_degrees();
+
+        // perspective corners sit at half the fov vertically, the width follows the aspect ratio
+        let corner = cam.perspective_direction(-1.0, 1.0, 2.0);
+        assert!((corner - Vec3::new(-2.0, 1.0, -1.0).normalize()).length() < 1e-5, "{corner}");
+
+        // the pixel grid of a 1000 x 500 image, jitter moves rays by less than 0.2 degrees
+        let (_, top_left) = cam.generate_ray(0, 0, 1000, 500, rng).unwrap();
+        assert!(angle(top_left, Vec3::new(-2.0, 1.0, -1.0)) < 0.2, "{top_left}");
+        let (_, bottom_right) = cam.generate_ray(999, 499, 1000, 500, rng).unwrap();
+        assert!(angle(bottom_right, Vec3::new(2.0, -1.0, -1.0)) < 0.2, "{bottom_right}");
+
+        cam.projection = Projection::Orthographic { height: 4.0 };
+        let (origin, direction) = cam.generate_ray(0, 0, 1000, 500, rng).unwrap();
+        assert!((origin - Vec3::new(-4.0, 2.0, 0.0)).length() < 0.02 && direction == Vec3::NEG_Z, "{origin}");
+
+        // the fisheye circle touches the top and bottom edges, its corners are black
+        cam.projection = Projection::Fisheye;
+        cam.fov = 180.0;
+        assert!(cam.generate_ray(0, 0, 1000, 1000, rng).is_none());
+        let (_, top) = cam.generate_ray(500, 0, 1000, 1000, rng).unwrap();
+        assert!(angle(top, Vec3::Y) < 0.2, "{top}");
+        let (_, center) = cam.generate_ray(500, 500, 1000, 1000, rng).unwrap();
+        assert!(angle(center, Vec3::NEG_Z) < 0.2, "{center}");
+
+        // the panorama wraps around behind the camera at its left and right edges
+        cam.projection = Projection::Equirectangular;
+        let (_, left) = cam.generate_ray(0, 250, 1000, 500, rng).unwrap();
+        assert!(angle(left, Vec3::Z) < 0.5, "{left}");
+        let (_, top) = cam.generate_ray(500, 0, 1000, 500, rng).unwrap();
+        assert!(angle(top, Vec3::Y) < 0.5, "{top}");
+        let (_, right) = cam.generate_ray(750, 250, 1000, 500, rng).unwrap();
+        assert!(angle(right, Vec3::X) < 0.5, "{right}");
+    }
 
-    let r = u1.sqrt();
-    let theta = 2.0 * std::f32::consts::PI * u2;
-    let x = r * theta.cos();
-    let y = r * theta.sin();
-    let z = (1.0 - u1).sqrt(); // ensures x^2 + y^2 + z^2 = 1
+    #[test]
+    fn stereo_eyes_converge_on_the_focus_plane() {
+        let rng = &mut rand::rng();
+        let mut cam = camera(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y, 60.0);
+        cam.focus_distance = 5.0;
+        cam.projection = Projection::Stereo { eye_separation: 0.1, panoramic: false };
+
+        // the same pixel in either half looks at the same point on the focus plane
+        let (left_origin, left) = cam.generate_ray(250, 250, 1000, 500, rng).unwrap();
+        let (right_origin, right) = cam.generate_ray(750, 250, 1000, 500, rng).unwrap();
+        assert!((left_origin - Vec3::new(-0.05, 0.0, 0.0)).length() < 1e-6);
+        assert!((right_origin - Vec3::new(0.05, 0.0, 0.0)).length() < 1e-6);
+        let left_point = left_origin + left * (5.0 / -left.z);
+        let right_point = right_origin + right * (5.0 / -right.z);
+        assert!((left_point - right_point).length() < 0.05, "{left_point} {right_point}");
+    }
 
-    // build tangent space
-    let (t, b) = tangent_space(n);
+    #[test]
+    fn unit_emitter_lights_a_diffuse_plane() {
+        // a huge sphere just above the floor covers almost all of its sky
+        let light = Material { emission: Vec3::ONE, specular: 0.0, ..Default::default() };
+        let ceiling = Sphere { center: Vec3::new(0.0, 1001.0, 0.0), radius: 1000.0, material: &light };
+        let gray = Material { color: Vec3::splat(127.5), roughness: 1.0, specular: 0.0, ..Default::default() };
+        let floor = Plane { point: Vec3::ZERO, normal: Vec3::Y, material: &gray };
+        let scene = Scene::new(vec![&ceiling, &floor], Vec::new());
+
+        // seen directly the emitter shows its own radiance
+        let up = camera(Vec3::new(0.0, 0.5, 0.0), Vec3::new(0.0, 2.0, 0.0), Vec3::Z, 10.0);
+        let direct = average(&render_image(&up, &scene, 4, 4, 4, false));
+        assert!((direct - Vec3::ONE).abs().max_element() < 1e-4, "{direct}");
+
+        // a 50% gray diffuse floor under a radiance of 1 reflects about 0.5, a bit more
+        // from the Burley retro-reflection and the emitter's grazing Fresnel reflection
+        let down = camera(Vec3::new(0.0, 0.5, 0.0), Vec3::ZERO, Vec3::Z, 10.0);
+        let lit = average(&render_image(&down, &scene, 8, 8, 64, false));
+        assert!((lit - Vec3::splat(0.53)).abs().max_element() < 0.03, "{lit}");
+    }
 
-    // world-space direction
-    let dir = (t * x) + (b * y) + (n * z);
-    dir.normalize()
-}
+    #[test]
+    fn small_lights_match_the_analytic_irradiance() {
+        // a sphere of radius 0.5 whose center is 2.5 above the floor covers a cone with sin^2 = 0.04,
+        // the floor under it receives pi * 0.04 and reflects 0.5 / pi of that straight up
+        let gray = Material { color: Vec3::splat(127.5), roughness: 1.0, specular: 0.0, ..Default::default() };
+        let floor = Plane { point: Vec3::ZERO, normal: Vec3::Y, material: &gray };
+        let glow = Material { emission: Vec3::ONE, specular: 0.0, ..Default::default() };
+        let emitter = Sphere { center: Vec3::new(0.0, 2.5, 0.0), radius: 0.5, material: &glow };
+        let light = lights::Light::Sphere(lights::SphereLight { position: Vec3::new(0.0, 2.5, 0.0), radius: 0.5, intensity: 1.0, color: Vec3::splat(255.0) });
+        let down = camera(Vec3::new(0.0, 0.5, 0.0), Vec3::ZERO, Vec3::Z, 10.0);
+
+        // light sampling and bsdf sampling both find these, MIS has to count them once
+        for scene in [Scene::new(vec![&floor, &emitter], Vec::new()), Scene::new(vec![&floor], vec![&light])] {
+            let lit = average(&render_image(&down, &scene, 16, 16, 256, false));
+            assert!((lit - Vec3::splat(0.02)).abs().max_element() < 0.0006, "{lit}");
+        }
+    }
 
-fn tangent_space(n: Vec3) -> (Vec3, Vec3) {
-    // choose helper vector to avoid degenerate cross
-    let helper = if n.x.abs() > 0.1 { Vec3::Y } else { Vec3::X };
-    let tangent = n.cross(helper).normalize();
-    let bitangent = n.cross(tangent);
-    (tangent, bitangent)
-}
+    #[test]
+    fn light_passes_add_up_to_the_color() {
+        // nothing the camera sees glows itself, so all of the color went through the passes
+        let light = Material { emission: Vec3::ONE, specular: 0.0, ..Default::default() };
+        let lamp = Sphere { center: Vec3::new(1.0, 2.0, -1.0), radius: 0.5, material: &light };
+        let gray = Material { color: Vec3::splat(127.5), roughness: 0.3, ..Default::default() };
+        let floor = Plane { point: Vec3::ZERO, normal: Vec3::Y, material: &gray };
+        let ball = Sphere { center: Vec3::new(0.0, 0.5, -0.5), radius: 0.5, material: &gray };
+        let scene = Scene::new(vec![&lamp, &floor, &ball], Vec::new());
+        let down = camera(Vec3::new(0.0, 1.5, 1.0), Vec3::new(0.0, 0.0, -0.5), Vec3::Y, 40.0);
+
+        let image = render_image(&down, &scene, 6, 6, 8, true);
+        let pass = |name: &str| &image.layers.iter().find(|layer| layer.name == name).unwrap().pixels;
+        for (i, &color) in image.color.iter().enumerate() {
+            let passes = pass("direct_diffuse")[i] + pass("direct_specular")[i] + pass("indirect_diffuse")[i] + pass("indirect_specular")[i];
+            assert!((passes - color).abs().max_element() < 1e-4 * color.max_element().max(1.0), "{passes} {color}");
+        }
+        assert!(image.color.iter().any(|c| c.x > 0.0));
+        // the floor and the ball share their material
+        let ids: Vec<f32> = pass("material_id").iter().map(|id| id.x).collect();
+        assert!(ids.iter().all(|&id| id == 2.0), "{ids:?}");
+    }
 
-fn pack_color(c: Vec3) -> u32 {
-    let gamma = 2.2;
-    // gamma correction
-    let corrected = Vec3::new(
-        c.x.clamp(0.0, 1.0).powf(1.0 / gamma),
-        c.y.clamp(0.0, 1.0).powf(1.0 / gamma),
-        c.z.clamp(0.0, 1.0).powf(1.0 / gamma),
-    );
-
-    let r = (corrected.x * 255.0) as u8;
-    let g = (corrected.y * 255.0) as u8;
-    let b = (corrected.z * 255.0) as u8;
-    let a = 255u8;
-
-    ((a as u32) << 24) | ((b as u32) << 16) | ((g as u32) << 8) | (r as u32)
+    #[test]
+    fn roulette_keeps_long_paths_unbiased() {
+        // a glowing gray ceiling over a gray floor, light bounces between the two without end
+        let light = Material { color: Vec3::splat(127.5), roughness: 1.0, specular: 0.0, emission: Vec3::ONE, ..Default::default() };
+        let gray = Material { color: Vec3::splat(127.5), roughness: 1.0, specular: 0.0, ..Default::default() };
+        let ceiling = Plane { point: Vec3::new(0.0, 1.0, 0.0), normal: Vec3::NEG_Y, material: &light };
+        let floor = Plane { point: Vec3::ZERO, normal: Vec3::Y, material: &gray };
+        let mut scene = Scene::new(vec![&ceiling, &floor], Vec::new());
+        let down = camera(Vec3::new(0.0, 0.5, 0.0), Vec3::ZERO, Vec3::Z, 10.0);
+        let mut render = |max_depth: u32| {
+            scene.max_depth = max_depth;
+            average(&render_image(&down, &scene, 16, 16, 256, false)).x
+        };
+
+        // paths this short end before Russian roulette starts, every round trip between the
+        // planes scales the light by about the same factor, so the rest of the series follows
+        let one_bounce = render(1);
+        let three_bounces = render(3);
+        let ratio = (three_bounces - one_bounce) / one_bounce;
+        let expected = one_bounce + (three_bounces - one_bounce) / (1.0 - ratio);
+
+        let unlimited = render(64);
+        assert!((unlimited - expected).abs() < 0.01, "{unlimited} {expected}");
+    }
 }
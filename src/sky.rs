@@ -0,0 +1,293 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+use rand::prelude::*;
+
+use crate::environment::{EnvironmentMap, EnvironmentSample};
+use crate::loaders::hdr::HdrImage;
+
+// Preetham et al. 1999 luminances are in kcd/m^2, this brings a clear zenith to roughly 1.0
+const LUMINANCE_SCALE: f32 = 1.0 / 30.0;
+// luminance of the sun disk above the atmosphere, in kcd/m^2
+const SUN_LUMINANCE: f32 = 1.6e6;
+// apparent radius of the sun in degrees
+const SUN_RADIUS: f32 = 0.2665;
+// resolution of the baked sky used for importance sampling
+const BAKE_WIDTH: usize = 128;
+const BAKE_HEIGHT: usize = 64;
+
+// Analytic daylight after Preetham, Shirley and Smits 1999, with the sun as a small bright disk
+// +y is up, north is -z and east is +x, the ground below the horizon stays black
+pub struct Sky {
+    // unit vector towards the sun
+    pub sun_direction: Vec3,
+    pub intensity: f32,
+    zenith: Vec3,
+    perez: [[f32; 5]; 3],
+    sun_radiance: Vec3,
+    // sky without the sun, only used to pick directions
+    sampling_map: EnvironmentMap,
+}
+
+impl Sky {
+    // Sun elevation above the horizon and azimuth clockwise from north, both in degrees
+    pub fn new(sun_elevation: f32, sun_azimuth: f32, turbidity: f32, intensity: f32) -> Self {
+        let elevation = sun_elevation.to_radians();
+        let azimuth = sun_azimuth.to_radians();
+        let sun_direction = Vec3::new(
+            azimuth.sin() * elevation.cos(),
+            elevation.sin(),
+            -azimuth.cos() * elevation.cos(),
+        );
+
+        let turbidity = turbidity.clamp(1.7, 10.0);
+        let theta_s = sun_direction.y.clamp(-1.0, 1.0).acos().min(PI * 0.5);
+        let t = turbidity;
+
+        // zenith luminance and chromaticity
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+
+        let theta2 = theta_s * theta_s;
+        let theta3 = theta2 * theta_s;
+        let zenith_x = t * t * (0.00166 * theta3 - 0.00375 * theta2 + 0.00209 * theta_s)
+            + t * (-0.02903 * theta3 + 0.06377 * theta2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * theta3 - 0.21196 * theta2 + 0.06052 * theta_s + 0.25886);
+        let zenith_y = t * t * (0.00275 * theta3 - 0.00610 * theta2 + 0.00317 * theta_s)
+            + t * (-0.04214 * theta3 + 0.08970 * theta2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * theta3 - 0.26756 * theta2 + 0.06670 * theta_s + 0.26688);
+
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        // the perez functions are relative to their value at the zenith
+        let zenith = Vec3::new(
+            zenith_luminance / perez_function(&perez[0], 0.0, theta_s),
+            zenith_x / perez_function(&perez[1], 0.0, theta_s),
+            zenith_y / perez_function(&perez[2], 0.0, theta_s),
+        );
+
+        Sky {
+            sun_direction,
+            intensity,
+            zenith,
+            perez,
+            sun_radiance: sun_radiance(theta_s, turbidity),
+            sampling_map: bake_sampling_map(|direction| sky_radiance(zenith, &perez, sun_direction, direction)),
+        }
+    }
+
+    // Sun position for a place on earth, latitude and longitude in degrees (north and east positive),
+    // day of the year starting at 1 and the time of day in hours UTC
+    pub fn from_location(latitude: f32, longitude: f32, day_of_year: u32, utc_hours: f32, turbidity: f32, intensity: f32) -> Self {
+        let (elevation, azimuth) = solar_position(latitude, longitude, day_of_year, utc_hours);
+        Sky::new(elevation, azimuth, turbidity, intensity)
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let direction = direction.normalize();
+        let mut radiance = sky_radiance(self.zenith, &self.perez, self.sun_direction, direction);
+
+        if direction.dot(self.sun_direction) >= SUN_RADIUS.to_radians().cos() && self.sun_direction.y > 0.0 {
+            radiance += self.sun_radiance;
+        }
+        radiance * self.intensity
+    }
+
+    // Samples the sun disk half of the time, the rest follows the baked sky
+    pub fn sample(&self, rng: &mut ThreadRng) -> Option<EnvironmentSample> {
+        let direction = if self.sun_direction.y > 0.0 && rng.random::<f32>() < 0.5 {
+            let cos_max = SUN_RADIUS.to_radians().cos();
+            let cos_theta = 1.0 - rng.random::<f32>() * (1.0 - cos_max);
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * rng.random::<f32>();
+            let (u_axis, v_axis) = self.sun_direction.any_orthonormal_pair();
+            self.sun_direction * cos_theta + (u_axis * phi.cos() + v_axis * phi.sin()) * sin_theta
+        } else {
+            self.sampling_map.sample(rng)?.direction
+        };
+
+        let pdf = self.pdf(direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(EnvironmentSample { direction, radiance: self.radiance(direction), pdf })
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let direction = direction.normalize();
+        if self.sun_direction.y <= 0.0 {
+            return self.sampling_map.pdf(direction);
+        }
+
+        let cos_max = SUN_RADIUS.to_radians().cos();
+        let sun_pdf = if direction.dot(self.sun_direction) >= cos_max {
+            1.0 / (2.0 * PI * (1.0 - cos_max))
+        } else {
+            0.0
+        };
+        0.5 * sun_pdf + 0.5 * self.sampling_map.pdf(direction)
+    }
+}
+
+// Sky without the sun disk, zenith holds the luminance and chromaticity divided by their perez value at the zenith
+fn sky_radiance(zenith: Vec3, perez: &[[f32; 5]; 3], sun_direction: Vec3, direction: Vec3) -> Vec3 {
+    if direction.y <= 0.0 {
+        return Vec3::ZERO;
+    }
+
+    let theta = direction.y.clamp(-1.0, 1.0).acos();
+    let gamma = direction.dot(sun_direction).clamp(-1.0, 1.0).acos();
+
+    let luminance = zenith.x * perez_function(&perez[0], theta, gamma);
+    let x = zenith.y * perez_function(&perez[1], theta, gamma);
+    let y = zenith.z * perez_function(&perez[2], theta, gamma);
+
+    xyy_to_rgb(x, y, luminance * LUMINANCE_SCALE)
+}
+
+// Tabulates the sky in the EnvironmentMap layout so it can share its importance sampling
+fn bake_sampling_map(radiance: impl Fn(Vec3) -> Vec3) -> EnvironmentMap {
+    let mut pixels = Vec::with_capacity(BAKE_WIDTH * BAKE_HEIGHT);
+    for y in 0..BAKE_HEIGHT {
+        for x in 0..BAKE_WIDTH {
+            // the center column looks down -z
+            let phi = ((x as f32 + 0.5) / BAKE_WIDTH as f32 - 0.5) * 2.0 * PI;
+            let theta = (y as f32 + 0.5) / BAKE_HEIGHT as f32 * PI;
+            pixels.push(radiance(Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())));
+        }
+    }
+    EnvironmentMap::new(HdrImage { width: BAKE_WIDTH, height: BAKE_HEIGHT, pixels }, 1.0, 0.0)
+}
+
+fn perez_function(coefficients: &[f32; 5], theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coefficients;
+    let cos_theta = theta.cos().max(0.01);
+    let cos_gamma = gamma.cos();
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+// CIE xyY to linear sRGB
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Vec3 {
+    if y <= 0.0 {
+        return Vec3::ZERO;
+    }
+    let cie_x = x / y * luminance;
+    let cie_z = (1.0 - x - y) / y * luminance;
+
+    Vec3::new(
+        3.2406 * cie_x - 1.5372 * luminance - 0.4986 * cie_z,
+        -0.9689 * cie_x + 1.8758 * luminance + 0.0415 * cie_z,
+        0.0557 * cie_x - 0.2040 * luminance + 1.0570 * cie_z,
+    )
+    .max(Vec3::ZERO)
+}
+
+// Sun color after the trip through the atmosphere, Rayleigh and aerosol extinction
+// at a representative wavelength per channel and the Kasten-Young air mass
+fn sun_radiance(theta_s: f32, turbidity: f32) -> Vec3 {
+    let zenith_degrees = theta_s.to_degrees();
+    let air_mass = 1.0 / (theta_s.cos() + 0.50572 * (96.07995 - zenith_degrees).powf(-1.6364));
+
+    let beta = 0.04608 * turbidity - 0.04586;
+    let transmittance = [0.680f32, 0.550, 0.440].map(|micrometers| {
+        let rayleigh = 0.008735 * micrometers.powf(-4.08);
+        let aerosol = beta * micrometers.powf(-1.3);
+        (-(rayleigh + aerosol) * air_mass).exp()
+    });
+
+    Vec3::from(transmittance) * (SUN_LUMINANCE * LUMINANCE_SCALE)
+}
+
+// Elevation and azimuth (clockwise from north) of the sun in degrees,
+// after the NOAA general solar position approximation
+pub fn solar_position(latitude: f32, longitude: f32, day_of_year: u32, utc_hours: f32) -> (f32, f32) {
+    let gamma = 2.0 * PI / 365.0 * (day_of_year.max(1) as f32 - 1.0 + (utc_hours - 12.0) / 24.0);
+
+    let equation_of_time = 229.18 * (0.000075 + 0.001868 * gamma.cos() - 0.032077 * gamma.sin()
+        - 0.014615 * (2.0 * gamma).cos() - 0.040849 * (2.0 * gamma).sin());
+    let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2.0 * gamma).cos() + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos() + 0.00148 * (3.0 * gamma).sin();
+
+    // true solar time in minutes gives the hour angle, zero at local noon
+    let solar_minutes = utc_hours * 60.0 + equation_of_time + 4.0 * longitude;
+    let hour_angle = (solar_minutes / 4.0 - 180.0).to_radians();
+    let latitude = latitude.to_radians();
+
+    let cos_zenith = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    let elevation = 90.0 - cos_zenith.clamp(-1.0, 1.0).acos().to_degrees();
+
+    let azimuth = hour_angle.sin().atan2(hour_angle.cos() * latitude.sin() - declination.tan() * latitude.cos());
+    (elevation, (azimuth.to_degrees() + 180.0).rem_euclid(360.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Radiance over the upper hemisphere by quadrature, plus the sun disk which the grid is too coarse for
+    fn integral(sky: &Sky) -> Vec3 {
+        let steps = 600;
+        let mut total = Vec3::ZERO;
+        for i in 0..steps {
+            let cos_theta = (i as f32 + 0.5) / steps as f32;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..steps {
+                let phi = (j as f32 + 0.5) / steps as f32 * 2.0 * PI;
+                let direction = Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                total += sky_radiance(sky.zenith, &sky.perez, sky.sun_direction, direction);
+            }
+        }
+        let sun_solid_angle = 2.0 * PI * (1.0 - SUN_RADIUS.to_radians().cos());
+        (total * 2.0 * PI / (steps * steps) as f32 + sky.sun_radiance * sun_solid_angle) * sky.intensity
+    }
+
+    #[test]
+    fn importance_sampling_is_consistent() {
+        let rng = &mut rand::rng();
+        let sky = Sky::new(35.0, 120.0, 3.0, 0.5);
+        let n = 200_000;
+        let mut estimate = Vec3::ZERO;
+        let mut dropped = 0;
+        for _ in 0..n {
+            // the renderer counts a missing sample as no light, rounding at pixel edges may cause one
+            let Some(sample) = sky.sample(rng) else {
+                dropped += 1;
+                continue;
+            };
+            assert!(sample.direction.y > 0.0, "{}", sample.direction);
+            assert!((sample.pdf - sky.pdf(sample.direction)).abs() <= 1e-4 * sample.pdf);
+            estimate += sample.radiance / sample.pdf;
+        }
+        assert!(dropped < 10, "{dropped}");
+        let estimate = estimate / n as f32;
+        let expected = integral(&sky);
+        assert!(((estimate - expected) / expected).abs().max_element() < 0.02, "{estimate} {expected}");
+    }
+
+    #[test]
+    fn ground_is_black() {
+        let sky = Sky::new(20.0, 0.0, 2.5, 1.0);
+        assert_eq!(sky.radiance(Vec3::new(0.3, -0.1, 0.2)), Vec3::ZERO);
+        assert!(sky.radiance(Vec3::Y).min_element() > 0.0);
+        // the sun sits 20 degrees up towards north (-z)
+        let sun = sky.radiance(Vec3::new(0.0, 20.0f32.to_radians().sin(), -20.0f32.to_radians().cos()));
+        assert!(sun.x > 100.0 * sky.radiance(Vec3::Y).x);
+    }
+
+    #[test]
+    fn solar_position_at_the_equinox() {
+        // around the march equinox the sun stands overhead at the equator at noon and rises due east
+        let (elevation, _) = solar_position(0.0, 0.0, 80, 12.0);
+        assert!(elevation > 88.0, "{elevation}");
+        let (elevation, azimuth) = solar_position(0.0, 0.0, 80, 6.0);
+        assert!(elevation.abs() < 2.0 && (azimuth - 90.0).abs() < 2.0, "{elevation} {azimuth}");
+        // mid afternoon in the northern summer the sun is in the south west
+        let (elevation, azimuth) = solar_position(48.0, 0.0, 172, 15.0);
+        assert!(elevation > 30.0 && azimuth > 180.0 && azimuth < 270.0, "{elevation} {azimuth}");
+    }
+}
@@ -0,0 +1,49 @@
+use glam::{Vec2, Vec3};
+
+// Color image looked up by surface uv, linear values in 0..1 with rows from top to bottom
+pub struct Texture {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Texture {
+    // Bilinear lookup that repeats outside [0, 1], v = 0 is the bottom row like in obj files
+    pub fn sample(&self, uv: Vec2) -> Vec3 {
+        if self.width == 0 || self.height == 0 || !uv.is_finite() {
+            return Vec3::ONE;
+        }
+
+        // texel centers sit at half integer coordinates
+        let x = uv.x.rem_euclid(1.0) * self.width as f32 - 0.5;
+        let y = (1.0 - uv.y).rem_euclid(1.0) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let texel = |x: f32, y: f32| {
+            let x = (x as i64).rem_euclid(self.width as i64) as usize;
+            let y = (y as i64).rem_euclid(self.height as i64) as usize;
+            self.pixels[y * self.width + x]
+        };
+
+        let top = texel(x0, y0).lerp(texel(x0 + 1.0, y0), fx);
+        let bottom = texel(x0, y0 + 1.0).lerp(texel(x0 + 1.0, y0 + 1.0), fx);
+        top.lerp(bottom, fy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orientation_and_wrapping() {
+        // red top row, blue bottom row
+        let texture = Texture { width: 1, height: 2, pixels: vec![Vec3::X, Vec3::Z] };
+        assert_eq!(texture.sample(Vec2::new(0.5, 0.75)), Vec3::X);
+        assert_eq!(texture.sample(Vec2::new(0.5, 0.25)), Vec3::Z);
+        assert_eq!(texture.sample(Vec2::new(3.5, -0.75)), Vec3::Z);
+        // halfway between the texel centers
+        assert_eq!(texture.sample(Vec2::new(0.5, 0.5)), Vec3::new(0.5, 0.0, 0.5));
+    }
+}
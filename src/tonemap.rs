@@ -0,0 +1,184 @@
+use glam::{Mat3, Vec3};
+
+// Curve that squeezes scene radiance into the displayable range
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMap {
+    // values above 1 are cut off
+    Clamp,
+    // luminance based x / (1 + x), keeps the hue of bright colors
+    Reinhard,
+    // Stephen Hill's fit of the ACES reference and output transforms
+    Aces,
+    // Troy Sobotka's AgX, the polynomial fit of the default look
+    Agx,
+}
+
+// Scene linear radiance to display referred sRGB
+#[derive(Clone, Copy, Debug)]
+pub struct DisplayTransform {
+    pub tone_map: ToneMap,
+    // in stops, every +1 doubles the brightness before the curve
+    pub exposure: f32,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        DisplayTransform { tone_map: ToneMap::Clamp, exposure: 0.0 }
+    }
+}
+
+impl DisplayTransform {
+    // Encoded sRGB values in [0, 1]
+    pub fn apply(&self, c: Vec3) -> Vec3 {
+        // nan and negative radiance would poison the curves
+        let c = Vec3::select(c.cmpge(Vec3::ZERO), c, Vec3::ZERO) * 2.0f32.powf(self.exposure);
+
+        let encoded = match self.tone_map {
+            ToneMap::Clamp => srgb_oetf(c),
+            ToneMap::Reinhard => srgb_oetf(reinhard(c)),
+            ToneMap::Aces => srgb_oetf(aces_fitted(c)),
+            // the AgX curve already produces display encoded values
+            ToneMap::Agx => agx(c),
+        };
+        encoded.clamp(Vec3::ZERO, Vec3::ONE)
+    }
+
+    // 8 bits per channel, rounded to the nearest step
+    pub fn encode(&self, c: Vec3) -> [u8; 3] {
+        let c = self.apply(c) * 255.0 + 0.5;
+        [c.x as u8, c.y as u8, c.z as u8]
+    }
+}
+
+// IEC 61966-2-1 encoding with the linear toe
+pub fn srgb_oetf(c: Vec3) -> Vec3 {
+    let encode = |v: f32| {
+        let v = v.clamp(0.0, 1.0);
+        if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+    };
+    Vec3::new(encode(c.x), encode(c.y), encode(c.z))
+}
+
+// Inverse of srgb_oetf, for 8 bit images that store encoded colors
+pub fn srgb_eotf(c: Vec3) -> Vec3 {
+    let decode = |v: f32| {
+        let v = v.clamp(0.0, 1.0);
+        if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+    };
+    Vec3::new(decode(c.x), decode(c.y), decode(c.z))
+}
+
+fn reinhard(c: Vec3) -> Vec3 {
+    let luminance = c.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+    if luminance <= 0.0 {
+        return Vec3::ZERO;
+    }
+    c * (1.0 / (1.0 + luminance))
+}
+
+fn aces_fitted(c: Vec3) -> Vec3 {
+    // sRGB to the rrt working space and back, written as rows
+    let input = Mat3::from_cols_array_2d(&[
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ])
+    .transpose();
+    let output = Mat3::from_cols_array_2d(&[
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ])
+    .transpose();
+
+    let v = input * c;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.432951) + 0.238081;
+    output * (a / b)
+}
+
+fn agx(c: Vec3) -> Vec3 {
+    // inset into the AgX working space, given column by column
+    let inset = Mat3::from_cols_array(&[
+        0.84247906, 0.042328242, 0.042375655,
+        0.0784336, 0.87846864, 0.0784336,
+        0.079223745, 0.07916613, 0.879143,
+    ]);
+    let outset = Mat3::from_cols_array(&[
+        1.196879, -0.052896852, -0.052971636,
+        -0.09802088, 1.1519031, -0.09804345,
+        -0.09902974, -0.098961177, 1.1510737,
+    ]);
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    // log encoding over the 16.5 stops the curve covers
+    let v = inset * c;
+    let v = Vec3::new(v.x.max(1e-10).log2(), v.y.max(1e-10).log2(), v.z.max(1e-10).log2());
+    let x = (v.clamp(Vec3::splat(MIN_EV), Vec3::splat(MAX_EV)) - MIN_EV) / (MAX_EV - MIN_EV);
+
+    // sigmoid contrast curve as a 6th order polynomial
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+
+    outset * curve
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVES: [ToneMap; 4] = [ToneMap::Clamp, ToneMap::Reinhard, ToneMap::Aces, ToneMap::Agx];
+
+    #[test]
+    fn srgb_round_trip() {
+        assert_eq!(srgb_oetf(Vec3::ZERO), Vec3::ZERO);
+        assert!((srgb_oetf(Vec3::ONE) - Vec3::ONE).abs().max_element() < 1e-6);
+        assert!((srgb_oetf(Vec3::splat(0.5)).x - 0.735357).abs() < 1e-5);
+        // the linear toe and the power segment meet
+        assert!((srgb_oetf(Vec3::splat(0.0031308)).x - 0.04045).abs() < 1e-5);
+        for i in 0..=100 {
+            let v = Vec3::splat(i as f32 / 100.0);
+            assert!((srgb_eotf(srgb_oetf(v)) - v).abs().max_element() < 1e-5, "{v}");
+        }
+    }
+
+    #[test]
+    fn curves_stay_in_range_and_keep_the_order() {
+        for tone_map in CURVES {
+            let display = DisplayTransform { tone_map, exposure: 0.0 };
+            let mut previous = -1.0;
+            for i in 0..200 {
+                let value = display.apply(Vec3::splat(0.001 * 1.08f32.powi(i))).y;
+                assert!((0.0..=1.0).contains(&value) && value >= previous, "{tone_map:?} {i} {value}");
+                previous = value;
+            }
+            // black stays black, invalid radiance too
+            assert!(display.apply(Vec3::ZERO).max_element() < 0.01, "{tone_map:?}");
+            assert_eq!(display.apply(Vec3::new(f32::NAN, -1.0, f32::NAN)), display.apply(Vec3::ZERO));
+            // bright highlights end up near white
+            assert!(display.apply(Vec3::splat(1000.0)).min_element() > 0.9, "{tone_map:?}");
+        }
+    }
+
+    #[test]
+    fn exposure_scales_before_the_curve() {
+        for tone_map in CURVES {
+            let brighter = DisplayTransform { tone_map, exposure: 1.0 }.apply(Vec3::new(0.1, 0.2, 0.05));
+            let doubled = DisplayTransform { tone_map, exposure: 0.0 }.apply(Vec3::new(0.2, 0.4, 0.1));
+            assert!((brighter - doubled).abs().max_element() < 1e-5, "{tone_map:?}");
+        }
+        let display = DisplayTransform { tone_map: ToneMap::Clamp, exposure: -1.0 };
+        assert_eq!(display.encode(Vec3::splat(2.0)), [255, 255, 255]);
+        assert_eq!(display.encode(Vec3::splat(1.0)), [188, 188, 188]);
+    }
+
+    #[test]
+    fn reinhard_keeps_the_hue() {
+        let c = Vec3::new(8.0, 4.0, 1.0);
+        let mapped = reinhard(c);
+        assert!((mapped / mapped.x - c / c.x).abs().max_element() < 1e-6);
+        assert!(mapped.dot(Vec3::new(0.2126, 0.7152, 0.0722)) < 1.0);
+    }
+}